@@ -0,0 +1,176 @@
+use crate::conf::RETRANS_TIMER;
+use crate::error::Error;
+use crate::neighbors::neighbor_usable;
+use crate::netlink::{NetlinkSocket, dump_routes, host_route, parse_host_route};
+use crate::types::*;
+use log::{debug, info, warn};
+use std::net::Ipv6Addr;
+
+/// installs /128 routes for the neighbors confirmed on downstream interfaces,
+/// so that the host forwards the traffic of proxied addresses to the right interface
+///     0. NDProxies ask for a route once they answer a NS for a confirmed neighbor
+///     1. the route is removed once the neighbor is no longer usable
+/// the routes are made with RTPROT_NDPROXY, and the existing routes of others are left alone
+pub struct Autowire {
+    socket: NetlinkSocket,
+    receiver: AutowireReceiver,
+    routes: SharedHostRoutes,
+    neighbors_cache: NeighborsCache,
+    kernel_neighbors: SharedKernelNeighbors,
+}
+
+impl Autowire {
+    pub fn new(
+        receiver: AutowireReceiver,
+        routes: SharedHostRoutes,
+        neighbors_cache: NeighborsCache,
+        kernel_neighbors: SharedKernelNeighbors,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            socket: NetlinkSocket::new(0)?,
+            receiver,
+            routes,
+            neighbors_cache,
+            kernel_neighbors,
+        })
+    }
+
+    /// main loop: install the requested routes, and remove the ones of gone neighbors
+    pub async fn run(mut self) -> Result<(), Error> {
+        warn!("Autowire: Start to work.");
+        self.remove_leftovers().await?;
+        let mut interval = tokio::time::interval(RETRANS_TIMER);
+        loop {
+            tokio::select! {
+                // the branch is disabled once every NDProxy has gone
+                Some((addr, scope_id)) = self.receiver.recv() => self.wire(addr, scope_id).await,
+                _ = interval.tick() => self.unwire_gone().await,
+            }
+        }
+    }
+
+    /// the routes made by the last run are stale, the NDProxies will ask for them again
+    async fn remove_leftovers(&mut self) -> Result<(), Error> {
+        let leftovers: Vec<_> = self
+            .socket
+            .dump(dump_routes())
+            .await?
+            .iter()
+            .filter_map(parse_host_route)
+            .collect();
+        for (addr, scope_id) in leftovers {
+            info!(
+                "Autowire: Remove the stale route of {} to scope id {}.",
+                addr, scope_id
+            );
+            unwire(&mut self.socket, addr, scope_id).await?;
+        }
+        Ok(())
+    }
+
+    async fn wire(&mut self, addr: Ipv6Addr, scope_id: u32) {
+        let old = self.routes.lock().unwrap().get(&addr).copied();
+        if old == Some(scope_id) {
+            return;
+        }
+        // a neighbor that moves to another interface moves its route
+        if let Some(old_scope_id) = old {
+            if let Err(e) = unwire(&mut self.socket, addr, old_scope_id).await {
+                warn!(
+                    "Autowire: _{:?}_ Failed to remove the route of {}.",
+                    e, addr
+                );
+            }
+            self.routes.lock().unwrap().remove(&addr);
+        }
+        let msg = host_route(
+            libc::RTM_NEWROUTE,
+            (libc::NLM_F_CREATE | libc::NLM_F_EXCL) as u16,
+            addr,
+            scope_id,
+        );
+        match self.socket.request(msg).await {
+            Ok(()) => {
+                info!("Autowire: Route {}/128 to scope id {}.", addr, scope_id);
+                self.routes.lock().unwrap().insert(addr, scope_id);
+            }
+            // someone else has routed it, leave it alone
+            Err(Error::Netlink(libc::EEXIST)) => {
+                debug!("Autowire: {}/128 is routed already, skip it.", addr)
+            }
+            Err(e) => warn!(
+                "Autowire: _{:?}_ Failed to route {}/128 to scope id {}.",
+                e, addr, scope_id
+            ),
+        }
+    }
+
+    async fn unwire_gone(&mut self) {
+        let gone: Vec<_> = self
+            .routes
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(addr, scope_id)| {
+                !neighbor_usable(
+                    &self.neighbors_cache,
+                    &self.kernel_neighbors,
+                    &(**scope_id, **addr),
+                )
+            })
+            .map(|(addr, scope_id)| (*addr, *scope_id))
+            .collect();
+        for (addr, scope_id) in gone {
+            info!("Autowire: {} has gone, remove its route.", addr);
+            if let Err(e) = unwire(&mut self.socket, addr, scope_id).await {
+                warn!(
+                    "Autowire: _{:?}_ Failed to remove the route of {}.",
+                    e, addr
+                );
+            }
+            self.routes.lock().unwrap().remove(&addr);
+        }
+    }
+}
+
+async fn unwire(socket: &mut NetlinkSocket, addr: Ipv6Addr, scope_id: u32) -> Result<(), Error> {
+    match socket
+        .request(host_route(libc::RTM_DELROUTE, 0, addr, scope_id))
+        .await
+    {
+        // the kernel removes the routes of vanished interfaces by itself
+        Err(Error::Netlink(libc::ESRCH)) => Ok(()),
+        result => result,
+    }
+}
+
+/// remove every route installed by Autowire, on exit
+pub async fn unwire_all(routes: SharedHostRoutes) -> Result<(), Error> {
+    unwire_where(&routes, |_, _| true).await
+}
+
+/// remove the routes of (address, scope id) picked by f, such as the ones of a stopped rule
+pub async fn unwire_where(
+    routes: &SharedHostRoutes,
+    f: impl Fn(Ipv6Addr, u32) -> bool,
+) -> Result<(), Error> {
+    let routes: Vec<_> = routes
+        .lock()
+        .unwrap()
+        .extract_if(|addr, scope_id| f(*addr, *scope_id))
+        .collect();
+    if routes.is_empty() {
+        return Ok(());
+    }
+    let mut socket = NetlinkSocket::new(0)?;
+    for (addr, scope_id) in routes {
+        info!("Autowire: Remove the route of {}.", addr);
+        if let Err(e) = unwire(&mut socket, addr, scope_id).await {
+            warn!(
+                "Autowire: _{:?}_ Failed to remove the route of {}.",
+                e, addr
+            );
+        }
+    }
+    Ok(())
+}
@@ -1,6 +1,16 @@
 use crate::error::Error;
-use crate::types::{AddressMangling, Proxy};
+use crate::hosts;
+use crate::interfaces::IfaceSelector;
+use crate::types::{
+    AddressMangling, AdvertisedHwaddr, DadPolicy, LeaseFormat, NeighborOracle, OverridePolicy,
+    Proxy,
+};
 use ipnet::Ipv6Net;
+use log::{LevelFilter, info, warn};
+use pnet::util::MacAddr;
+use std::collections::BTreeMap;
+use std::net::Ipv6Addr;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
 #[derive(getset::Getters, Debug, PartialEq, Eq, Clone)]
@@ -11,84 +21,414 @@ pub struct NDConfig {
     proxy_type: Proxy,
     #[get = "pub with_prefix"]
     proxied_pfx: Ipv6Net,
+    /// the proxied prefix follows the global address of this interface,
+    /// proxied_pfx is ::/length until the address shows up
     #[get = "pub with_prefix"]
-    proxied_ifaces: Vec<String>,
+    proxied_pfx_iface: Option<String>,
     #[get = "pub with_prefix"]
-    forwarded_ifaces: Vec<String>,
+    proxied_ifaces: IfaceSelector,
+    #[get = "pub with_prefix"]
+    forwarded_ifaces: IfaceSelector,
     #[get = "pub with_prefix"]
     address_mangling: AddressMangling,
     #[get = "pub with_prefix"]
     dst_pfx: Ipv6Net,
+    /// static mode only: the targets answered for, None for all of proxied_pfx
+    #[get = "pub with_prefix"]
+    hosts: Option<Vec<Ipv6Net>>,
+    /// static mode only: more targets, read from the file whenever it changes
+    #[get = "pub with_prefix"]
+    hosts_file: Option<PathBuf>,
+    /// the lease file of a DHCPv6 server, whose leased addresses are known neighbors
+    #[get = "pub with_prefix"]
+    leases: Option<(PathBuf, LeaseFormat)>,
+    /// max number of unreachable targets to remember
+    #[get = "pub with_prefix"]
+    negative_cache_capacity: usize,
+    /// how long an unreachable target is not solicited again
+    #[get = "pub with_prefix"]
+    negative_hold_down: Duration,
+    /// the hold-down time doubles on every failure, up to this value
+    #[get = "pub with_prefix"]
+    negative_max_hold_down: Duration,
+    /// forward mode only: resolve targets myself, or trust the neighbor table of the kernel
+    #[get = "pub with_prefix"]
+    neighbor_oracle: NeighborOracle,
+    /// how the NSes of Duplicate Address Detection are handled
+    #[get = "pub with_prefix"]
+    dad_policy: DadPolicy,
+    /// the Router flag of the proxied NAs
+    #[get = "pub with_prefix"]
+    router: bool,
+    /// the Override flag of the proxied NAs
+    #[get = "pub with_prefix"]
+    override_policy: OverridePolicy,
+    /// the link-layer address advertised for the targets
+    #[get = "pub with_prefix"]
+    advertised_hwaddr: AdvertisedHwaddr,
+    /// the NAs are sent after na_delay plus a random time up to na_jitter
+    #[get = "pub with_prefix"]
+    na_delay: Duration,
+    #[get = "pub with_prefix"]
+    na_jitter: Duration,
+    /// how many NAs wait for their delay, the oldest ones are dropped beyond it
+    #[get = "pub with_prefix"]
+    na_queue_depth: usize,
+    /// stop answering for a target advertised by someone else with another MAC, for a while
+    #[get = "pub with_prefix"]
+    conflict_hold_time: Duration,
+    /// forward mode only: install /128 routes for the confirmed neighbors
+    #[get = "pub with_prefix"]
+    autowire: bool,
+    /// forward mode only: learn the neighbors from the traffic on downstream interfaces
+    #[get = "pub with_prefix"]
+    learning: bool,
+    /// let the kernel answer NSes for the proxied addresses, via proxy neighbor entries
+    #[get = "pub with_prefix"]
+    kernel_offload: bool,
+    /// how many NSes wait for me, queue_depth of [global] by default
+    #[get = "pub with_prefix"]
+    queue_depth: usize,
+    /// this rule overlaps the others on purpose, and takes the NSes of its prefix from them
+    overrides: bool,
+    /// the file that the rule comes from
+    #[get = "pub with_prefix"]
+    source: PathBuf,
 }
 
 const PROXY_FORWARD_STRING: &str = "forward";
+const PROXY_STATIC_STRING: &str = "static";
+/// in forwarded_ifaces, the downstream interface is determined by the routing table of the host
+pub const FORWARD_AUTO_STRING: &str = "auto";
 const ADDRESS_NETMAP_STRING: &str = "netmap";
 const ADDRESS_NPT_STRING: &str = "npt";
+const ORACLE_NDPROXY_STRING: &str = "ndproxy";
+const ORACLE_KERNEL_STRING: &str = "kernel";
+const LEASES_DNSMASQ_STRING: &str = "dnsmasq";
+const LEASES_ISC_STRING: &str = "isc";
+const LEASES_KEA_STRING: &str = "kea";
+const DAD_DEFEND_STRING: &str = "defend";
+const DAD_SILENT_STRING: &str = "silent";
+const DAD_FORWARD_STRING: &str = "forward";
+const OVERRIDE_NEVER_STRING: &str = "never";
+const OVERRIDE_ALWAYS_STRING: &str = "always";
+const HWADDR_IFACE_STRING: &str = "iface";
+const HWADDR_NEIGHBOR_STRING: &str = "neighbor";
+
+/// the sections of the config file
+const GLOBAL_SECTION: &str = "global";
+const RULES_SECTION: &str = "ndp";
+/// the files that more rules are taken from
+const INCLUDE_KEY: &str = "include";
 
-// TODO: magic number or set it in config file?
+/// defaults of the tunables in [global]
+/// how long an unused STALE or FAILED neighbor stays in cache
 pub const TTL_OF_CACHE: Duration = Duration::from_secs(600);
 pub const MPSC_CAPACITY: usize = 1;
+/// large enough for a NS or NA on Ethernet
+const RECV_BUFFER_SIZE: usize = 1500;
+/// neighbors learned per second on an interface, the others wait for the next packets
+const LEARNING_RATE: u32 = 100;
+/// node constants of RFC 4861 section 10,
+/// MAX_MULTICAST_SOLICIT is used for MAX_UNICAST_SOLICIT as well
+pub const MAX_MULTICAST_SOLICIT: u32 = 3;
+pub const REACHABLE_TIME: Duration = Duration::from_secs(30);
+pub const RETRANS_TIMER: Duration = Duration::from_secs(1);
+pub const DELAY_FIRST_PROBE_TIME: Duration = Duration::from_secs(5);
+pub const MAX_ANYCAST_DELAY_TIME: Duration = Duration::from_secs(1);
+/// delayed NAs of a rule, enough for the NSes of a busy link within MAX_ANYCAST_DELAY_TIME
+pub const NA_QUEUE_DEPTH: usize = 256;
+/// how long the NAs seen are remembered, as long as the longest conflict_hold_time,
+/// and longer than na_delay plus na_jitter can be
+pub const OBSERVED_NA_TTL: Duration = Duration::from_secs(CONFLICT_HOLD_TIMES.1);
+/// spreads the refreshes of the neighbors that went STALE together
+const REFRESH_JITTER: Duration = Duration::from_secs(5);
+/// defaults of the negative cache
+const NEGATIVE_CACHE_CAPACITY: u64 = 4096;
+const NEGATIVE_HOLD_DOWN_SECS: u64 = 5;
+const NEGATIVE_MAX_HOLD_DOWN_SECS: u64 = 300;
+/// RFC 4861 drops ND packets with any other hop limit, so it is not tunable
+pub const ND_HOP_LIMIT: u32 = 255;
+
+/// the tunables in section "global", shared by every rule
+#[derive(getset::CopyGetters, Debug, PartialEq, Eq, Clone, Copy)]
+pub struct GlobalConfig {
+    /// how long an unused neighbor or proxy entry is kept, rules can override it
+    #[get_copy = "pub with_prefix"]
+    cache_ttl: Duration,
+    /// capacity of the queues between tasks, rules can override it for their NSes
+    #[get_copy = "pub with_prefix"]
+    queue_depth: usize,
+    /// size of the buffer receiving NSes and NAs
+    #[get_copy = "pub with_prefix"]
+    recv_buffer_size: usize,
+    /// how many NSes are sent before a neighbor is considered unreachable
+    #[get_copy = "pub with_prefix"]
+    ns_retransmits: u32,
+    /// interval between the NSes, RetransTimer in RFC 4861
+    #[get_copy = "pub with_prefix"]
+    retrans_timer: Duration,
+    /// how long before its expiry an unused neighbor is solicited again, zero for never
+    #[get_copy = "pub with_prefix"]
+    refresh_interval: Duration,
+    /// the refreshes start up to this much earlier, at random
+    #[get_copy = "pub with_prefix"]
+    refresh_jitter: Duration,
+    /// overrides RUST_LOG for ndproxy
+    #[get_copy = "pub with_prefix"]
+    log_level: Option<LevelFilter>,
+    /// how many neighbors a learning monitor records per second, on each interface
+    #[get_copy = "pub with_prefix"]
+    learning_rate: u32,
+}
+
+impl Default for GlobalConfig {
+    fn default() -> Self {
+        Self {
+            cache_ttl: TTL_OF_CACHE,
+            queue_depth: MPSC_CAPACITY,
+            recv_buffer_size: RECV_BUFFER_SIZE,
+            ns_retransmits: MAX_MULTICAST_SOLICIT,
+            retrans_timer: RETRANS_TIMER,
+            refresh_interval: Duration::ZERO,
+            refresh_jitter: REFRESH_JITTER,
+            log_level: None,
+            learning_rate: LEARNING_RATE,
+        }
+    }
+}
+
+impl GlobalConfig {
+    pub fn new(value: Option<config::Value>) -> Result<Self, Error> {
+        let section = GLOBAL_SECTION;
+        let mut ret = Self::default();
+        let Some(value) = value else {
+            return Ok(ret);
+        };
+        let mut config_table = value
+            .into_table()
+            .map_err(|e| invalid(section, "section", e))?;
+
+        /*
+         * neighbor cache:
+         * unused neighbors are removed after cache_ttl seconds,
+         * a neighbor is unreachable after ns_retransmits NSes sent every retrans_timer milliseconds
+         */
+        if let Some(v) = config_table.remove("cache_ttl") {
+            ret.cache_ttl = Duration::from_secs(parse_uint(section, "cache_ttl", v, CACHE_TTL)?);
+        }
+        if let Some(v) = config_table.remove("ns_retransmits") {
+            ret.ns_retransmits = parse_uint(section, "ns_retransmits", v, NS_RETRANSMITS)? as u32;
+        }
+        if let Some(v) = config_table.remove("retrans_timer") {
+            ret.retrans_timer =
+                Duration::from_millis(parse_uint(section, "retrans_timer", v, RETRANS_TIMER_MS)?);
+        }
+
+        /*
+         * refresh:
+         * a STALE neighbor is solicited by unicast refresh_interval seconds before it expires,
+         * and up to refresh_jitter seconds earlier, so that the hosts alive never fall out of cache
+         */
+        if let Some(v) = config_table.remove("refresh_interval") {
+            ret.refresh_interval = Duration::from_secs(parse_uint(
+                section,
+                "refresh_interval",
+                v,
+                REFRESH_INTERVALS,
+            )?);
+        }
+        if let Some(v) = config_table.remove("refresh_jitter") {
+            ret.refresh_jitter =
+                Duration::from_secs(parse_uint(section, "refresh_jitter", v, REFRESH_JITTERS)?);
+        }
+
+        /*
+         * queues and buffers:
+         * queue_depth NSes wait for a NDProxy before the NSMonitors drop them,
+         * recv_buffer_size has to hold a NS or NA with its options
+         */
+        if let Some(v) = config_table.remove("queue_depth") {
+            ret.queue_depth = parse_uint(section, "queue_depth", v, QUEUE_DEPTH)? as usize;
+        }
+        if let Some(v) = config_table.remove("recv_buffer_size") {
+            ret.recv_buffer_size =
+                parse_uint(section, "recv_buffer_size", v, RECV_BUFFER_SIZES)? as usize;
+        }
+
+        /*
+         * learning mode:
+         * the learning monitors record at most learning_rate neighbors per second on each interface
+         */
+        if let Some(v) = config_table.remove("learning_rate") {
+            ret.learning_rate = parse_uint(section, "learning_rate", v, LEARNING_RATES)? as u32;
+        }
+
+        /*
+         * log level of ndproxy, RUST_LOG is used if it is not specified
+         */
+        if let Some(v) = config_table.remove("log_level") {
+            let level = v
+                .into_string()
+                .map_err(|e| invalid(section, "log_level", e))?;
+            ret.log_level = Some(level.parse().map_err(|_| {
+                invalid(
+                    section,
+                    "log_level",
+                    format!(
+                        "unknown level \"{}\", expected one of off, error, warn, info, debug, trace",
+                        level
+                    ),
+                )
+            })?);
+        }
+
+        // typos should not be ignored silently
+        reject_unknown_keys(section, "", config_table)?;
+        Ok(ret)
+    }
+
+    /// how long a forwarded NS waits for its NA, RetransTimer * MAX_MULTICAST_SOLICIT in RFC 4861
+    pub fn pending_ns_ttl(&self) -> Duration {
+        self.retrans_timer * self.ns_retransmits
+    }
+}
+
+/// the valid ranges of the tunables
+const CACHE_TTL: (u64, u64) = (1, 86400 * 365);
+const NS_RETRANSMITS: (u64, u64) = (1, 100);
+const RETRANS_TIMER_MS: (u64, u64) = (10, 60000);
+const REFRESH_INTERVALS: (u64, u64) = (0, 86400 * 365);
+const REFRESH_JITTERS: (u64, u64) = (0, 3600);
+const QUEUE_DEPTH: (u64, u64) = (1, 65536);
+const RECV_BUFFER_SIZES: (u64, u64) = (1280, 65535);
+const LEARNING_RATES: (u64, u64) = (1, 1000000);
+const NA_DELAY_MS: (u64, u64) = (0, MAX_ANYCAST_DELAY_TIME.as_millis() as u64);
+const CONFLICT_HOLD_TIMES: (u64, u64) = (0, 3600);
+const NEGATIVE_CACHE_CAPACITIES: (u64, u64) = (1, 1 << 20);
+const NEGATIVE_HOLD_DOWNS: (u64, u64) = (1, 86400);
+/// the keys that make no sense for the other type of rules
+const STATIC_ONLY_KEYS: &[&str] = &["hosts", "hosts_file"];
+const FORWARD_ONLY_KEYS: &[&str] = &[
+    "negative_cache_capacity",
+    "negative_hold_down",
+    "negative_max_hold_down",
+    "neighbor_oracle",
+    "autowire",
+    "learning",
+];
 
 impl NDConfig {
-    pub fn new(name: String, value: config::Value) -> Result<Self, Error> {
-        let mut config_table = value.into_table()?;
+    pub fn new(name: String, value: config::Value, global: &GlobalConfig) -> Result<Self, Error> {
+        let section = format!("{}.{}", RULES_SECTION, name);
+        let mut config_table = value
+            .into_table()
+            .map_err(|e| invalid(&section, "section", e))?;
         /*
          * there must be a field for "type",
          * so that we can decide the way to proxy Neighbor Discoverys
          */
-        let proxy_type_string = config_table.remove("type").unwrap().into_string()?;
-
-        let proxy_type = if proxy_type_string == PROXY_FORWARD_STRING {
-            Proxy::Forward
-        } else {
-            Proxy::Static
+        let proxy_type = match config_table
+            .remove("type")
+            .ok_or_else(|| invalid(&section, "type", "missing"))?
+            .into_string()
+            .map_err(|e| invalid(&section, "type", e))?
+            .as_str()
+        {
+            PROXY_FORWARD_STRING => Proxy::Forward,
+            PROXY_STATIC_STRING => Proxy::Static,
+            other => {
+                return Err(invalid(
+                    &section,
+                    "type",
+                    format!(
+                        "unknown type \"{}\", expected \"{}\" or \"{}\"",
+                        other, PROXY_FORWARD_STRING, PROXY_STATIC_STRING
+                    ),
+                ));
+            }
+        };
+        /*
+         * the keys for the other type are rejected, rather than ignored silently
+         */
+        let (other_keys, reason) = match proxy_type {
+            Proxy::Static => (FORWARD_ONLY_KEYS, "for forward rules only"),
+            Proxy::Forward => (STATIC_ONLY_KEYS, "for static rules only"),
         };
+        if let Some(key) = other_keys
+            .iter()
+            .find(|key| config_table.contains_key(**key))
+        {
+            return Err(invalid(&section, key, reason));
+        }
 
         /*
          * there must be a field for "proxied_prefix",
          * to inform us which prefix is going to be proxied
+         * it could be taken from the global address of an interface,
+         * for the prefixes delegated by ISPs that change on reconnect:
+         * ```
+         * proxied_prefix = { from_iface = "wan0", length = 64 }
+         * ```
          */
-        let proxied_pfx: Ipv6Net = config_table
+        let proxied_prefix = config_table
             .remove("proxied_prefix")
-            .unwrap()
-            .into_string()?
-            .parse()?;
-        // TODO: is it necessary to check the prefix length and address type?
+            .ok_or_else(|| invalid(&section, "proxied_prefix", "missing"))?;
+        let (proxied_pfx, proxied_pfx_iface): (Ipv6Net, _) =
+            match proxied_prefix.clone().into_table() {
+                Ok(mut table) => {
+                    let length = table
+                        .remove("length")
+                        .ok_or_else(|| invalid(&section, "proxied_prefix.length", "missing"))?
+                        .into_uint()
+                        .map_err(|e| invalid(&section, "proxied_prefix.length", e))?;
+                    let from_iface = table
+                        .remove("from_iface")
+                        .ok_or_else(|| invalid(&section, "proxied_prefix.from_iface", "missing"))?
+                        .into_string()
+                        .map_err(|e| invalid(&section, "proxied_prefix.from_iface", e))?;
+                    reject_unknown_keys(&section, "proxied_prefix.", table)?;
+                    (
+                        u8::try_from(length)
+                            .ok()
+                            .and_then(|length| Ipv6Net::new(Ipv6Addr::UNSPECIFIED, length).ok())
+                            .ok_or_else(|| {
+                                invalid(&section, "proxied_prefix.length", "must be 0 to 128")
+                            })?,
+                        Some(from_iface),
+                    )
+                }
+                Err(_) => (
+                    parse_prefix(&section, "proxied_prefix", proxied_prefix)?,
+                    None,
+                ),
+            };
 
         /*
          * get the interfaces whose Neighbor Solicitations are proxied by me
          * if it is not specified, I will listen on all of the interfaces
          */
         let proxied_ifaces = match config_table.remove("proxied_ifaces") {
-            Some(v) => match v.clone().into_array() {
-                Ok(if_vec) => if_vec
-                    .into_iter()
-                    // TODO: at leaset leave some messages here
-                    .map(|iface| iface.into_string().unwrap())
-                    .collect(),
-                Err(_) => vec![v.into_string()?],
-            },
+            Some(v) => parse_strings(&section, "proxied_ifaces", v)?,
             None => vec![String::from("*")],
         };
+        let proxied_ifaces = IfaceSelector::new(&proxied_ifaces)
+            .map_err(|e| invalid(&section, "proxied_ifaces", e))?;
 
         /*
          * get the interfaces whose Neighbor Advertisements are proxied by me
          * if it is not specified, I will listen on all of the interfaces
+         * static rules do not forward anything, so it is ignored there
          */
-        let forwarded_ifaces = match proxy_type {
-            Proxy::Static => [].into(),
-            Proxy::Forward => match config_table.remove("forwarded_ifaces") {
-                Some(v) => match v.clone().into_array() {
-                    Ok(if_vec) => if_vec
-                        .into_iter()
-                        // TODO: at leaset leave some messages here
-                        .map(|iface| iface.into_string().unwrap())
-                        .collect(),
-                    Err(_) => vec![v.into_string()?],
-                },
-                None => vec![String::from("*")],
-            },
+        let forwarded_ifaces = match config_table.remove("forwarded_ifaces") {
+            Some(v) => parse_strings(&section, "forwarded_ifaces", v)?,
+            None => vec![String::from("*")],
         };
+        let forwarded_ifaces = IfaceSelector::new(match proxy_type {
+            Proxy::Static => &[],
+            Proxy::Forward => &forwarded_ifaces,
+        })
+        .map_err(|e| invalid(&section, "forwarded_ifaces", e))?;
 
         /*
          * extra recipe: rewrite the address
@@ -109,94 +449,1295 @@ impl NDConfig {
         let address_mangling;
         match config_table.remove("rewrite_method") {
             Some(v) => {
-                let how_to_mangle = v.into_string()?;
-                dst_pfx = config_table
-                    .remove("local_prefix")
-                    .unwrap()
-                    .into_string()?
-                    .parse()?;
-                if how_to_mangle == ADDRESS_NETMAP_STRING {
-                    address_mangling = AddressMangling::Netmap;
-                } else if how_to_mangle == ADDRESS_NPT_STRING {
-                    address_mangling = AddressMangling::Npt;
-                } else {
-                    address_mangling = AddressMangling::Nochange;
+                address_mangling = match v
+                    .into_string()
+                    .map_err(|e| invalid(&section, "rewrite_method", e))?
+                    .as_str()
+                {
+                    ADDRESS_NETMAP_STRING => AddressMangling::Netmap,
+                    ADDRESS_NPT_STRING => AddressMangling::Npt,
+                    other => {
+                        return Err(invalid(
+                            &section,
+                            "rewrite_method",
+                            format!(
+                                "unknown method \"{}\", expected \"{}\" or \"{}\"",
+                                other, ADDRESS_NETMAP_STRING, ADDRESS_NPT_STRING
+                            ),
+                        ));
+                    }
+                };
+                dst_pfx = parse_prefix(
+                    &section,
+                    "local_prefix",
+                    config_table
+                        .remove("local_prefix")
+                        .ok_or_else(|| invalid(&section, "local_prefix", "missing"))?,
+                )?;
+                // both of them keep the host part, so the lengths have to match
+                if dst_pfx.prefix_len() != proxied_pfx.prefix_len() {
+                    return Err(invalid(
+                        &section,
+                        "local_prefix",
+                        format!(
+                            "the length of {} differs from the proxied prefix /{}",
+                            dst_pfx,
+                            proxied_pfx.prefix_len()
+                        ),
+                    ));
+                }
+                // NPTv6 adjusts the 16-bit word right after the prefix
+                if address_mangling == AddressMangling::Npt
+                    && (dst_pfx.prefix_len() % 16 != 0 || dst_pfx.prefix_len() > 112)
+                {
+                    return Err(invalid(
+                        &section,
+                        "local_prefix",
+                        format!(
+                            "npt needs a prefix length of a multiple of 16, up to 112, got /{}",
+                            dst_pfx.prefix_len()
+                        ),
+                    ));
                 }
             }
             None => {
+                if config_table.contains_key("local_prefix") {
+                    return Err(invalid(&section, "local_prefix", "requires rewrite_method"));
+                }
                 dst_pfx = proxied_pfx;
                 address_mangling = AddressMangling::Nochange;
             }
         }
 
+        /*
+         * DHCPv6 leases:
+         * the active IA_NA leases of a local DHCPv6 server are known neighbors until they end,
+         * forward rules answer for them without soliciting downstream interfaces,
+         * static rules answer for them besides the hosts
+         * ```
+         * leases = { file = "/var/lib/misc/dnsmasq.leases", format = "dnsmasq" }
+         * ```
+         * the leased addresses are the local ones, translated back by rewrite_method
+         */
+        let leases = match config_table.remove("leases") {
+            Some(_) if proxied_pfx_iface.is_some() => {
+                return Err(invalid(
+                    &section,
+                    "leases",
+                    "requires a fixed proxied_prefix",
+                ));
+            }
+            Some(v) => {
+                let mut table = v.into_table().map_err(|e| invalid(&section, "leases", e))?;
+                let file = table
+                    .remove("file")
+                    .ok_or_else(|| invalid(&section, "leases.file", "missing"))?
+                    .into_string()
+                    .map_err(|e| invalid(&section, "leases.file", e))?;
+                let format = match table
+                    .remove("format")
+                    .ok_or_else(|| invalid(&section, "leases.format", "missing"))?
+                    .into_string()
+                    .map_err(|e| invalid(&section, "leases.format", e))?
+                    .as_str()
+                {
+                    LEASES_DNSMASQ_STRING => LeaseFormat::Dnsmasq,
+                    LEASES_ISC_STRING => LeaseFormat::Isc,
+                    LEASES_KEA_STRING => LeaseFormat::Kea,
+                    other => {
+                        return Err(invalid(
+                            &section,
+                            "leases.format",
+                            format!(
+                                "unknown format \"{}\", expected \"{}\", \"{}\" or \"{}\"",
+                                other, LEASES_DNSMASQ_STRING, LEASES_ISC_STRING, LEASES_KEA_STRING
+                            ),
+                        ));
+                    }
+                };
+                reject_unknown_keys(&section, "leases.", table)?;
+                Some((PathBuf::from(file), format))
+            }
+            None => None,
+        };
+
+        /*
+         * hosts (static mode only):
+         * answer for these targets only, instead of the whole proxied prefix,
+         * so that the upstream does not take every address of the prefix as on-link
+         * ```
+         * hosts = [ "2001:db8::1", "2001:db8::100-2001:db8::1ff", "2001:db8::1:0/112" ]
+         * ```
+         * more of them could be read from a file, one per line, which is followed on change:
+         * ```
+         * hosts_file = "/var/lib/vms/hosts"
+         * ```
+         * a relative path is taken from the directory of the config file
+         */
+        for key in STATIC_ONLY_KEYS {
+            if config_table.contains_key(*key) && proxied_pfx_iface.is_some() {
+                return Err(invalid(&section, key, "requires a fixed proxied_prefix"));
+            }
+        }
+        let hosts_file = match config_table.remove("hosts_file") {
+            Some(v) => Some(PathBuf::from(
+                v.into_string()
+                    .map_err(|e| invalid(&section, "hosts_file", e))?,
+            )),
+            None => None,
+        };
+        let hosts = match config_table.remove("hosts") {
+            Some(v) => {
+                let mut prefixes = Vec::new();
+                for entry in parse_strings(&section, "hosts", v)? {
+                    for prefix in
+                        hosts::parse_hosts(&entry).map_err(|e| invalid(&section, "hosts", e))?
+                    {
+                        if !proxied_pfx.contains(&prefix) {
+                            return Err(invalid(
+                                &section,
+                                "hosts",
+                                format!("{} is out of {}", entry, proxied_pfx),
+                            ));
+                        }
+                        prefixes.push(prefix);
+                    }
+                }
+                Some(prefixes)
+            }
+            // the files alone tell the hosts
+            None if hosts_file.is_some() || leases.is_some() => Some(Vec::new()),
+            None => None,
+        };
+
+        /*
+         * negative cache (forward mode only):
+         * targets that failed address resolution are not solicited again for a while,
+         * so that scanning the proxied prefix does not flood the downstream interfaces
+         */
+        let negative_cache_capacity = match config_table.remove("negative_cache_capacity") {
+            Some(v) => parse_uint(
+                &section,
+                "negative_cache_capacity",
+                v,
+                NEGATIVE_CACHE_CAPACITIES,
+            )?,
+            None => NEGATIVE_CACHE_CAPACITY,
+        } as usize;
+        let negative_hold_down =
+            Duration::from_secs(match config_table.remove("negative_hold_down") {
+                Some(v) => parse_uint(&section, "negative_hold_down", v, NEGATIVE_HOLD_DOWNS)?,
+                None => NEGATIVE_HOLD_DOWN_SECS,
+            });
+        let negative_max_hold_down =
+            Duration::from_secs(match config_table.remove("negative_max_hold_down") {
+                Some(v) => parse_uint(&section, "negative_max_hold_down", v, NEGATIVE_HOLD_DOWNS)?,
+                None => NEGATIVE_MAX_HOLD_DOWN_SECS,
+            });
+        if negative_hold_down > negative_max_hold_down {
+            return Err(invalid(
+                &section,
+                "negative_hold_down",
+                format!(
+                    "{:?} is longer than negative_max_hold_down {:?}",
+                    negative_hold_down, negative_max_hold_down
+                ),
+            ));
+        }
+
+        /*
+         * neighbor oracle (forward mode only):
+         *   "ndproxy": solicit the targets on downstream interfaces myself
+         *   "kernel": ask the kernel to resolve the targets, and answer from its neighbor table,
+         *             so that I agree with what the host actually forwards to
+         */
+        let neighbor_oracle = match config_table.remove("neighbor_oracle") {
+            Some(v) => match v
+                .into_string()
+                .map_err(|e| invalid(&section, "neighbor_oracle", e))?
+                .as_str()
+            {
+                ORACLE_NDPROXY_STRING => NeighborOracle::Ndproxy,
+                ORACLE_KERNEL_STRING => NeighborOracle::Kernel,
+                other => {
+                    return Err(invalid(
+                        &section,
+                        "neighbor_oracle",
+                        format!(
+                            "unknown oracle \"{}\", expected \"{}\" or \"{}\"",
+                            other, ORACLE_NDPROXY_STRING, ORACLE_KERNEL_STRING
+                        ),
+                    ));
+                }
+            },
+            None => NeighborOracle::Ndproxy,
+        };
+
+        /*
+         * Duplicate Address Detection:
+         *   "defend": answer the DAD NSes for the targets I am answering for, with NAs to ff02::1
+         *   "silent": ignore them
+         *   "forward" (forward mode only): solicit the targets on downstream interfaces,
+         *             and defend the ones that are really there
+         */
+        let dad_policy = match config_table.remove("dad") {
+            Some(v) => match v
+                .into_string()
+                .map_err(|e| invalid(&section, "dad", e))?
+                .as_str()
+            {
+                DAD_DEFEND_STRING => DadPolicy::Defend,
+                DAD_SILENT_STRING => DadPolicy::Silent,
+                DAD_FORWARD_STRING if proxy_type == Proxy::Forward => DadPolicy::Forward,
+                DAD_FORWARD_STRING => {
+                    return Err(invalid(&section, "dad", "for forward rules only"));
+                }
+                other => {
+                    return Err(invalid(
+                        &section,
+                        "dad",
+                        format!(
+                            "unknown policy \"{}\", expected \"{}\", \"{}\" or \"{}\"",
+                            other, DAD_DEFEND_STRING, DAD_SILENT_STRING, DAD_FORWARD_STRING
+                        ),
+                    ));
+                }
+            },
+            None => DadPolicy::Defend,
+        };
+
+        /*
+         * flags of the proxied NAs, the Solicited flag follows the NS:
+         *   router: set the Router flag, for proxying routers (RFC 4389)
+         *   na_override: "never" leaves the cached link-layer addresses of the real owners alone
+         *                (RFC 4861 section 7.2.8), "always" replaces them
+         */
+        let router = match config_table.remove("router") {
+            Some(v) => v.into_bool().map_err(|e| invalid(&section, "router", e))?,
+            None => false,
+        };
+        let override_policy = match config_table.remove("na_override") {
+            Some(v) => match v
+                .into_string()
+                .map_err(|e| invalid(&section, "na_override", e))?
+                .as_str()
+            {
+                OVERRIDE_NEVER_STRING => OverridePolicy::Never,
+                OVERRIDE_ALWAYS_STRING => OverridePolicy::Always,
+                other => {
+                    return Err(invalid(
+                        &section,
+                        "na_override",
+                        format!(
+                            "unknown policy \"{}\", expected \"{}\" or \"{}\"",
+                            other, OVERRIDE_NEVER_STRING, OVERRIDE_ALWAYS_STRING
+                        ),
+                    ));
+                }
+            },
+            None => OverridePolicy::Never,
+        };
+
+        /*
+         * link-layer address of the proxied NAs:
+         *   "iface": the upstream interface
+         *   "neighbor" (forward mode only): the downstream neighbor, or the upstream interface if it is unknown
+         *   or a unicast MAC, e.g. the virtual MAC of VRRP
+         */
+        let advertised_hwaddr = match config_table.remove("na_hwaddr") {
+            Some(v) => match v
+                .into_string()
+                .map_err(|e| invalid(&section, "na_hwaddr", e))?
+                .as_str()
+            {
+                HWADDR_IFACE_STRING => AdvertisedHwaddr::Iface,
+                HWADDR_NEIGHBOR_STRING if proxy_type == Proxy::Forward => {
+                    AdvertisedHwaddr::Neighbor
+                }
+                HWADDR_NEIGHBOR_STRING => {
+                    return Err(invalid(&section, "na_hwaddr", "for forward rules only"));
+                }
+                other => match other.parse::<MacAddr>() {
+                    // neither multicast (nor broadcast) nor zero
+                    Ok(mac) if mac.0 & 0x01 == 0 && mac != MacAddr::zero() => {
+                        AdvertisedHwaddr::Fixed(mac)
+                    }
+                    Ok(mac) => {
+                        return Err(invalid(
+                            &section,
+                            "na_hwaddr",
+                            format!("{} is not a unicast MAC", mac),
+                        ));
+                    }
+                    Err(_) => {
+                        return Err(invalid(
+                            &section,
+                            "na_hwaddr",
+                            format!(
+                                "neither \"{}\", \"{}\" nor a MAC: \"{}\"",
+                                HWADDR_IFACE_STRING, HWADDR_NEIGHBOR_STRING, other
+                            ),
+                        ));
+                    }
+                },
+            },
+            None => AdvertisedHwaddr::Iface,
+        };
+
+        /*
+         * delay of NAs, in milliseconds:
+         * when other proxies or the real owners may answer for the same targets,
+         * the NAs should be delayed by a random time up to MAX_ANYCAST_DELAY_TIME (RFC 4861 section 7.2.7),
+         * and a delayed NA is cancelled if someone else advertises the target first
+         */
+        let na_delay = match config_table.remove("na_delay") {
+            Some(v) => Duration::from_millis(parse_uint(&section, "na_delay", v, NA_DELAY_MS)?),
+            None => Duration::ZERO,
+        };
+        let na_jitter = match config_table.remove("na_jitter") {
+            Some(v) => Duration::from_millis(parse_uint(&section, "na_jitter", v, NA_DELAY_MS)?),
+            None => Duration::ZERO,
+        };
+        if na_delay + na_jitter > MAX_ANYCAST_DELAY_TIME {
+            return Err(invalid(
+                &section,
+                "na_jitter",
+                format!(
+                    "na_delay plus na_jitter is longer than MAX_ANYCAST_DELAY_TIME {:?}",
+                    MAX_ANYCAST_DELAY_TIME
+                ),
+            ));
+        }
+        let na_queue_depth = match config_table.remove("na_queue_depth") {
+            Some(v) => parse_uint(&section, "na_queue_depth", v, QUEUE_DEPTH)? as usize,
+            None => NA_QUEUE_DEPTH,
+        };
+
+        /*
+         * address conflict detection, in seconds:
+         * the NAs on proxied_ifaces are watched, and a target advertised there with a MAC other than mine
+         * belongs to a real host on the upstream link, it is not answered for conflict_hold_time,
+         * 0 disables it
+         */
+        let conflict_hold_time = match config_table.remove("conflict_hold_time") {
+            Some(v) => Duration::from_secs(parse_uint(
+                &section,
+                "conflict_hold_time",
+                v,
+                CONFLICT_HOLD_TIMES,
+            )?),
+            None => Duration::ZERO,
+        };
+
+        /*
+         * autowire (forward mode only):
+         * install a /128 route towards the downstream interface for every confirmed target,
+         * so that the host forwards the traffic of proxied addresses to the right interface
+         */
+        let autowire = match config_table.remove("autowire") {
+            Some(v) => v
+                .into_bool()
+                .map_err(|e| invalid(&section, "autowire", e))?,
+            None => false,
+        };
+
+        /*
+         * learning (forward mode only):
+         * the global source addresses seen on downstream interfaces are recorded as neighbors,
+         * so that the hosts talking already are answered on the first NS
+         */
+        let learning = match config_table.remove("learning") {
+            Some(v) => v
+                .into_bool()
+                .map_err(|e| invalid(&section, "learning", e))?,
+            None => false,
+        };
+
+        /*
+         * kernel offload:
+         * once I answer a NS, a proxy entry is added to the upstream interface,
+         * so that the kernel answers the following NSes by itself.
+         * static rules offload their single hosts for good and their leased targets until the leases end,
+         * the entries of forward rules follow the neighbors
+         */
+        let kernel_offload = match config_table.remove("kernel_offload") {
+            Some(v) => v
+                .into_bool()
+                .map_err(|e| invalid(&section, "kernel_offload", e))?,
+            None => false,
+        };
+
+        /*
+         * tunables of [global] that make sense per rule
+         */
+        let queue_depth = match config_table.remove("queue_depth") {
+            Some(v) => parse_uint(&section, "queue_depth", v, QUEUE_DEPTH)? as usize,
+            None => global.queue_depth,
+        };
+
+        /*
+         * override:
+         * rules with the same proxied prefix are refused, and nested prefixes are warned,
+         * unless one of them is marked as an intentional overlap.
+         * a marked rule takes the NSes of its prefix, the other one of the same prefix is not started
+         * of nested rules, only the inner one can be marked, as it wins by longest match anyway
+         */
+        let overrides = match config_table.remove("override") {
+            Some(v) => v
+                .into_bool()
+                .map_err(|e| invalid(&section, "override", e))?,
+            None => false,
+        };
+
+        // typos should not be ignored silently
+        reject_unknown_keys(&section, "", config_table)?;
+
         Ok(NDConfig {
             name,
             proxy_type,
             proxied_pfx,
+            proxied_pfx_iface,
             proxied_ifaces,
             forwarded_ifaces,
             address_mangling,
             dst_pfx,
+            hosts,
+            hosts_file,
+            leases,
+            negative_cache_capacity,
+            negative_hold_down,
+            negative_max_hold_down,
+            neighbor_oracle,
+            dad_policy,
+            router,
+            override_policy,
+            advertised_hwaddr,
+            na_delay,
+            na_jitter,
+            na_queue_depth,
+            conflict_hold_time,
+            autowire,
+            learning,
+            kernel_offload,
+            queue_depth,
+            overrides,
+            source: PathBuf::new(),
         })
     }
+
+    /// whether the two configs differ in the interfaces only,
+    /// so that the change can be applied to a running NDProxy
+    pub fn same_except_ifaces(&self, other: &NDConfig) -> bool {
+        self.forwarded_ifaces.is_auto() == other.forwarded_ifaces.is_auto()
+            && NDConfig {
+                proxied_ifaces: self.proxied_ifaces.clone(),
+                forwarded_ifaces: self.forwarded_ifaces.clone(),
+                ..other.clone()
+            } == *self
+    }
+}
+
+/// the file is filled by Error::in_file()
+fn invalid(section: &str, key: &str, reason: impl ToString) -> Error {
+    Error::InvalidConfig {
+        file: String::new(),
+        section: section.to_string(),
+        key: key.to_string(),
+        reason: reason.to_string(),
+    }
+}
+
+/// an integer in the range of (min, max)
+fn parse_uint(
+    section: &str,
+    key: &str,
+    value: config::Value,
+    (min, max): (u64, u64),
+) -> Result<u64, Error> {
+    let v = value.into_uint().map_err(|e| invalid(section, key, e))?;
+    if v < min || v > max {
+        return Err(invalid(
+            section,
+            key,
+            format!("{} is out of range, must be {} to {}", v, min, max),
+        ));
+    }
+    Ok(v)
+}
+
+/// the keys left in the table are unknown to me
+fn reject_unknown_keys(
+    section: &str,
+    key_prefix: &str,
+    table: config::Map<String, config::Value>,
+) -> Result<(), Error> {
+    match table.keys().min() {
+        Some(key) => Err(invalid(
+            section,
+            &format!("{}{}", key_prefix, key),
+            "unknown key",
+        )),
+        None => Ok(()),
+    }
+}
+
+/// a prefix that NSes could be sent for
+fn parse_prefix(section: &str, key: &str, value: config::Value) -> Result<Ipv6Net, Error> {
+    let prefix: Ipv6Net = value
+        .into_string()
+        .map_err(|e| invalid(section, key, e))?
+        .parse()
+        .map_err(|e| invalid(section, key, e))?;
+    for (scope, range) in [("multicast", "ff00::/8"), ("link-local", "fe80::/10")] {
+        if range.parse::<Ipv6Net>().unwrap().contains(&prefix) {
+            return Err(invalid(
+                section,
+                key,
+                format!("{} is a {} prefix", prefix, scope),
+            ));
+        }
+    }
+    Ok(prefix)
+}
+
+/// a string or a list of strings
+fn parse_strings(section: &str, key: &str, value: config::Value) -> Result<Vec<String>, Error> {
+    match value.clone().into_array() {
+        Ok(if_vec) => if_vec
+            .into_iter()
+            .map(|iface| iface.into_string().map_err(|e| invalid(section, key, e)))
+            .collect(),
+        Err(_) => Ok(vec![
+            value.into_string().map_err(|e| invalid(section, key, e))?,
+        ]),
+    }
+}
+
+/// section "ndp" of a config file
+fn rules_table(
+    myconfig: &config::Config,
+    required: bool,
+) -> Result<config::Map<String, config::Value>, Error> {
+    match myconfig.get_table(RULES_SECTION) {
+        Ok(table) => Ok(table
+            .into_iter()
+            .collect::<BTreeMap<_, _>>()
+            .into_iter()
+            .collect()),
+        Err(config::ConfigError::NotFound(_)) if !required => Ok(config::Map::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// the sections and keys at the top level that are not in known
+fn reject_unknown_sections(myconfig: &config::Config, known: &[&str]) -> Result<(), Error> {
+    let table = myconfig
+        .cache
+        .clone()
+        .into_table()
+        .map_err(|e| invalid("", "", e))?;
+    match table
+        .keys()
+        .filter(|key| !known.contains(&key.as_str()))
+        .min()
+    {
+        Some(key) => Err(invalid(
+            "",
+            key,
+            format!(
+                "unknown section or key, expected one of {}",
+                known.join(", ")
+            ),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// the files matching the patterns in "include", sorted by their paths
+fn included_files(cfile: &str, myconfig: &config::Config) -> Result<Vec<PathBuf>, Error> {
+    let patterns = match myconfig.get::<config::Value>(INCLUDE_KEY) {
+        Ok(v) => parse_strings("", INCLUDE_KEY, v)?,
+        Err(config::ConfigError::NotFound(_)) => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let base = Path::new(cfile).parent().unwrap_or(Path::new(""));
+    let mut ret = Vec::new();
+    for pattern in patterns {
+        let mut path = base.join(&pattern);
+        if path.is_dir() {
+            path.push("*.toml");
+        } else if !pattern.contains(['*', '?', '[']) && !path.exists() {
+            return Err(invalid(
+                "",
+                INCLUDE_KEY,
+                format!("{} does not exist", path.display()),
+            ));
+        }
+        // a pattern matching nothing is fine, the directory may be empty for now
+        for entry in glob::glob(&path.to_string_lossy()).map_err(|e| invalid("", INCLUDE_KEY, e))? {
+            let entry = entry.map_err(|e| invalid("", INCLUDE_KEY, e))?;
+            if entry.is_file() && entry != Path::new(cfile) {
+                ret.push(entry);
+            }
+        }
+    }
+    ret.sort();
+    ret.dedup();
+    Ok(ret)
 }
 
-/// parse the toml configuration file, returns a vector of NDConfig
+/// parse the toml configuration file, returns [global] and the rules
 ///
-/// Note that there MUST be a master section called "ndp"
-pub fn parse_config(cfile: &str) -> Result<Vec<NDConfig>, Error> {
+/// Note that there MUST be a master section called "ndp", unless the rules are included from other files
+pub fn parse_config(cfile: &str) -> Result<(GlobalConfig, Vec<NDConfig>), Error> {
+    let myconfig = read_config(cfile)?;
+    let global = parse_global(cfile, &myconfig)?;
+    let rules = parse_rules(cfile, &myconfig, &global)?;
+    Ok((global, rules))
+}
+
+pub fn read_config(cfile: &str) -> Result<config::Config, Error> {
     let myconfig = config::Config::builder()
         .add_source(config::File::with_name(cfile))
-        .build()?;
+        .build()
+        .map_err(|e| Error::from(e).in_file(cfile))?;
+    reject_unknown_sections(&myconfig, &[GLOBAL_SECTION, RULES_SECTION, INCLUDE_KEY])
+        .map_err(|e| e.in_file(cfile))?;
+    Ok(myconfig)
+}
 
-    // TODO: magic word: ndp
-    // is it necessary?
-    let mut ret = Vec::new();
-    for item in myconfig
-        .get_table("ndp")?
-        .into_iter()
-        .map(|(key, value)| NDConfig::new(key, value))
-    {
-        ret.push(item?)
+/// parse section "global", which is optional
+pub fn parse_global(cfile: &str, myconfig: &config::Config) -> Result<GlobalConfig, Error> {
+    match myconfig.get::<config::Value>(GLOBAL_SECTION) {
+        Ok(v) => GlobalConfig::new(Some(v)),
+        Err(config::ConfigError::NotFound(_)) => GlobalConfig::new(None),
+        Err(e) => Err(e.into()),
+    }
+    .map_err(|e| e.in_file(cfile))
+}
+
+/// parse the rules in section "ndp" of the config file and the included files,
+/// and leave the overridden ones out
+pub fn parse_rules(
+    cfile: &str,
+    myconfig: &config::Config,
+    global: &GlobalConfig,
+) -> Result<Vec<NDConfig>, Error> {
+    /*
+     * more rules could be taken from other files, one per provisioning tool:
+     * ```
+     * include = [ "/etc/ndproxy.d", "/etc/ndproxy/rules-?.toml" ]
+     * ```
+     * a directory includes the *.toml files in it, and glob patterns are accepted,
+     * relative paths start from the directory of this file.
+     * the included files have [ndp.*] sections only, and are merged in the order of their paths
+     */
+    let included = included_files(cfile, myconfig).map_err(|e| e.in_file(cfile))?;
+    let mut sources = vec![(
+        PathBuf::from(cfile),
+        rules_table(myconfig, included.is_empty()).map_err(|e| e.in_file(cfile))?,
+    )];
+    for path in included {
+        let file = path.display().to_string();
+        let config = config::Config::builder()
+            .add_source(config::File::from(path.as_path()))
+            .build()
+            .map_err(|e| Error::from(e).in_file(&file))?;
+        reject_unknown_sections(&config, &[RULES_SECTION]).map_err(|e| e.in_file(&file))?;
+        sources.push((
+            path,
+            rules_table(&config, false).map_err(|e| e.in_file(&file))?,
+        ));
+    }
+
+    let mut ret: Vec<NDConfig> = Vec::new();
+    for (path, table) in sources {
+        let file = path.display().to_string();
+        for (name, value) in table {
+            if let Some(other) = ret.iter().find(|conf| conf.name == name) {
+                return Err(invalid(
+                    &format!("{}.{}", RULES_SECTION, name),
+                    "section",
+                    format!("the rule is defined in {} already", other.source.display()),
+                )
+                .in_file(&file));
+            }
+            let mut conf = NDConfig::new(name, value, global).map_err(|e| e.in_file(&file))?;
+            // relative to the directory of the config file
+            let base = path.parent().unwrap_or(Path::new(""));
+            conf.hosts_file = conf.hosts_file.map(|hosts_file| base.join(hosts_file));
+            conf.leases = conf.leases.map(|(file, format)| (base.join(file), format));
+            conf.source = path.clone();
+            ret.push(conf);
+        }
+    }
+    // sort them, so that the errors and logs do not change between runs
+    ret.sort_by(|a, b| a.name.cmp(&b.name));
+    let overridden = check_overlaps(&ret)?;
+    ret.retain(|conf| !overridden.contains(&conf.name));
+
+    // the NSes of a prefix go to the rule with the longest match
+    for conf in ret.iter() {
+        match &conf.proxied_pfx_iface {
+            Some(iface) => info!(
+                "Rule [ndp.{}] of {} proxies the /{} prefix taken from {}.",
+                conf.name,
+                conf.source.display(),
+                conf.proxied_pfx.prefix_len(),
+                iface
+            ),
+            None => info!(
+                "Rule [ndp.{}] of {} proxies {}{}.",
+                conf.name,
+                conf.source.display(),
+                conf.proxied_pfx.trunc(),
+                match &conf.hosts {
+                    Some(hosts) => format!(", {} prefixes of hosts only", hosts.len()),
+                    None => String::new(),
+                }
+            ),
+        }
     }
     Ok(ret)
 }
 
+/// rules with the same proxied prefix are errors, unless exactly one of them overrides,
+/// and so is an override on the outer one of nested rules, as the inner one wins by longest match,
+/// returns the names of the overridden rules
+///
+/// the prefixes taken from interfaces are unknown until they show up, so they are not checked here
+fn check_overlaps(configs: &[NDConfig]) -> Result<Vec<String>, Error> {
+    let fixed: Vec<_> = configs
+        .iter()
+        .filter(|conf| conf.proxied_pfx_iface.is_none())
+        .collect();
+    let mut overridden = Vec::new();
+    for (i, a) in fixed.iter().enumerate() {
+        for b in fixed[i + 1..].iter() {
+            let (a_pfx, b_pfx) = (a.proxied_pfx.trunc(), b.proxied_pfx.trunc());
+            if a_pfx == b_pfx {
+                let (winner, loser) = match (a.overrides, b.overrides) {
+                    (true, false) => (a, b),
+                    (false, true) => (b, a),
+                    _ => {
+                        return Err(invalid(
+                            &format!("{}.{}", RULES_SECTION, b.name),
+                            "proxied_prefix",
+                            format!(
+                                "{} is proxied by [ndp.{}] of {} too, set override = true on one of them",
+                                b_pfx,
+                                a.name,
+                                a.source.display()
+                            ),
+                        )
+                        .in_file(&b.source.display().to_string()));
+                    }
+                };
+                info!(
+                    "Rule [ndp.{}] overrides [ndp.{}] for {}, [ndp.{}] is not started.",
+                    winner.name, loser.name, a_pfx, loser.name
+                );
+                overridden.push(loser.name.clone());
+            } else if a_pfx.contains(&b_pfx) || b_pfx.contains(&a_pfx) {
+                let (outer, inner) = match a_pfx.contains(&b_pfx) {
+                    true => (a, b),
+                    false => (b, a),
+                };
+                // the longest match wins anyway, an outer rule cannot take the prefix of an inner one
+                if outer.overrides && !inner.overrides {
+                    return Err(invalid(
+                        &format!("{}.{}", RULES_SECTION, outer.name),
+                        "override",
+                        format!(
+                            "[ndp.{}] of {} proxies {} nested in it, and wins by longest match, set override = true on it instead",
+                            inner.name,
+                            inner.source.display(),
+                            inner.proxied_pfx.trunc()
+                        ),
+                    )
+                    .in_file(&outer.source.display().to_string()));
+                }
+                if inner.overrides {
+                    info!(
+                        "Rule [ndp.{}] takes {} from [ndp.{}].",
+                        inner.name,
+                        inner.proxied_pfx.trunc(),
+                        outer.name
+                    );
+                } else {
+                    warn!(
+                        "Rule [ndp.{}] ({}) is nested in [ndp.{}] ({}), [ndp.{}] wins by longest match, set override = true if it is intended.",
+                        inner.name,
+                        inner.proxied_pfx.trunc(),
+                        outer.name,
+                        outer.proxied_pfx.trunc(),
+                        inner.name
+                    );
+                }
+            }
+        }
+    }
+    Ok(overridden)
+}
+
 #[test]
 fn test_config_parser() {
-    let config1 = parse_config("test/test1.toml").unwrap().pop().unwrap();
-    let config2 = parse_config("test/test2.toml").unwrap().pop().unwrap();
-    let config3 = parse_config("test/test3.toml").unwrap().pop().unwrap();
+    let config1 = parse_config("test/test1.toml").unwrap().1.pop().unwrap();
+    let config2 = parse_config("test/test2.toml").unwrap().1.pop().unwrap();
+    let config3 = parse_config("test/test3.toml").unwrap().1.pop().unwrap();
+    let config4 = parse_config("test/test4.toml").unwrap().1.pop().unwrap();
+    let global1 = parse_config("test/test1.toml").unwrap().0;
+    let global2 = parse_config("test/test2.toml").unwrap().0;
+    let selector = |patterns: &[String]| IfaceSelector::new(patterns).unwrap();
 
     let result1 = NDConfig {
         name: "conf1".to_string(),
         proxy_type: Proxy::Forward,
         proxied_pfx: "2001:db8::/64".parse().unwrap(),
-        proxied_ifaces: vec![String::from("*")],
-        forwarded_ifaces: vec![String::from("*")],
+        proxied_pfx_iface: None,
+        proxied_ifaces: selector(&[String::from("*")]),
+        forwarded_ifaces: selector(&[String::from("*")]),
         address_mangling: AddressMangling::Nochange,
         dst_pfx: "2001:db8::/64".parse().unwrap(),
+        hosts: None,
+        hosts_file: None,
+        leases: None,
+        negative_cache_capacity: 4096,
+        negative_hold_down: Duration::from_secs(5),
+        negative_max_hold_down: Duration::from_secs(300),
+        neighbor_oracle: NeighborOracle::Ndproxy,
+        dad_policy: DadPolicy::Defend,
+        router: false,
+        override_policy: OverridePolicy::Never,
+        advertised_hwaddr: AdvertisedHwaddr::Iface,
+        na_delay: Duration::ZERO,
+        na_jitter: Duration::ZERO,
+        na_queue_depth: NA_QUEUE_DEPTH,
+        conflict_hold_time: Duration::ZERO,
+        autowire: false,
+        learning: false,
+        kernel_offload: false,
+        queue_depth: MPSC_CAPACITY,
+        overrides: false,
+        source: PathBuf::from("test/test1.toml"),
     };
     let result2 = NDConfig {
         name: "conf2".to_string(),
         proxy_type: Proxy::Forward,
         proxied_pfx: "2001:db8::/64".parse().unwrap(),
-        proxied_ifaces: vec![String::from("lo")],
-        forwarded_ifaces: vec![String::from("veth0")],
+        proxied_pfx_iface: None,
+        proxied_ifaces: selector(&[String::from("lo")]),
+        forwarded_ifaces: selector(&[String::from("veth0")]),
         address_mangling: AddressMangling::Netmap,
         dst_pfx: "2001:db9::/64".parse().unwrap(),
+        hosts: None,
+        hosts_file: None,
+        leases: None,
+        negative_cache_capacity: 16,
+        negative_hold_down: Duration::from_secs(10),
+        negative_max_hold_down: Duration::from_secs(60),
+        neighbor_oracle: NeighborOracle::Kernel,
+        dad_policy: DadPolicy::Forward,
+        router: true,
+        override_policy: OverridePolicy::Always,
+        advertised_hwaddr: AdvertisedHwaddr::Neighbor,
+        na_delay: Duration::ZERO,
+        na_jitter: Duration::from_millis(1000),
+        na_queue_depth: 64,
+        conflict_hold_time: Duration::from_secs(60),
+        autowire: true,
+        learning: true,
+        kernel_offload: true,
+        queue_depth: 16,
+        overrides: false,
+        source: PathBuf::from("test/test2.toml"),
     };
     let result3 = NDConfig {
         name: "conf3".to_string(),
         proxy_type: Proxy::Static,
         proxied_pfx: "2001:db8::/64".parse().unwrap(),
-        proxied_ifaces: vec![String::from("lo"), String::from("eth0")],
-        forwarded_ifaces: vec![],
+        proxied_pfx_iface: None,
+        proxied_ifaces: selector(&[String::from("lo"), String::from("eth0")]),
+        forwarded_ifaces: selector(&[]),
         address_mangling: AddressMangling::Npt,
         dst_pfx: "2001:db9::/64".parse().unwrap(),
+        hosts: Some(vec![
+            "2001:db8::1/128".parse().unwrap(),
+            "2001:db8::100/120".parse().unwrap(),
+        ]),
+        hosts_file: None,
+        leases: None,
+        negative_cache_capacity: 4096,
+        negative_hold_down: Duration::from_secs(5),
+        negative_max_hold_down: Duration::from_secs(300),
+        neighbor_oracle: NeighborOracle::Ndproxy,
+        dad_policy: DadPolicy::Defend,
+        router: false,
+        override_policy: OverridePolicy::Never,
+        advertised_hwaddr: AdvertisedHwaddr::Fixed(MacAddr::new(
+            0x00, 0x00, 0x5e, 0x00, 0x01, 0x0a,
+        )),
+        na_delay: Duration::ZERO,
+        na_jitter: Duration::ZERO,
+        na_queue_depth: NA_QUEUE_DEPTH,
+        conflict_hold_time: Duration::ZERO,
+        autowire: false,
+        learning: false,
+        kernel_offload: false,
+        queue_depth: MPSC_CAPACITY,
+        overrides: false,
+        source: PathBuf::from("test/test3.toml"),
     };
 
+    assert_eq!(global1, GlobalConfig::default());
+    assert_eq!(
+        global2,
+        GlobalConfig {
+            cache_ttl: Duration::from_secs(300),
+            queue_depth: 4,
+            recv_buffer_size: 9000,
+            ns_retransmits: 5,
+            retrans_timer: Duration::from_millis(500),
+            refresh_interval: Duration::from_secs(60),
+            refresh_jitter: Duration::from_secs(10),
+            log_level: Some(LevelFilter::Info),
+            learning_rate: 20,
+        }
+    );
+    assert_eq!(global2.pending_ns_ttl(), Duration::from_millis(2500));
     assert_eq!(config1, result1);
     assert_eq!(config2, result2);
     assert_eq!(config3, result3);
+    assert!(!config1.same_except_ifaces(&config2));
+    assert!(config1.same_except_ifaces(&NDConfig {
+        proxied_ifaces: selector(&[String::from("eth1")]),
+        ..config1.clone()
+    }));
+    assert_eq!(
+        config4.get_proxied_pfx(),
+        &"::/56".parse::<Ipv6Net>().unwrap()
+    );
+    assert_eq!(config4.get_proxied_pfx_iface().as_deref(), Some("wan0"));
+}
+
+#[test]
+fn test_config_validation() {
+    let parse = |rule: &str| {
+        let table = config::Config::builder()
+            .add_source(config::File::from_str(
+                &format!("[ndp.bad]\n{}", rule),
+                config::FileFormat::Toml,
+            ))
+            .build()
+            .unwrap()
+            .get_table("ndp")
+            .unwrap();
+        let (name, value) = table.into_iter().next().unwrap();
+        NDConfig::new(name, value, &GlobalConfig::default())
+    };
+    let rejected_key = |rule: &str| match parse(rule) {
+        Err(Error::InvalidConfig { section, key, .. }) if section == "ndp.bad" => key,
+        other => panic!("{:?} is accepted: {:?}", rule, other),
+    };
+
+    assert!(parse("type = \"static\"\nproxied_prefix = \"2001:db8::/64\"").is_ok());
+    for hosts in [
+        "\"2001:db9::1\"",
+        "\"2001:db8::2-2001:db8::1\"",
+        "\"2001:db8::ff-2001:db9::\"",
+        "\"2001:db8::/48\"",
+        "[ \"2001:db8::1\", \"zzz\" ]",
+    ] {
+        assert_eq!(
+            rejected_key(&format!(
+                "type = \"static\"\nproxied_prefix = \"2001:db8::/64\"\nhosts = {}",
+                hosts
+            )),
+            "hosts"
+        );
+    }
+    assert_eq!(
+        rejected_key(
+            "type = \"forward\"\nproxied_prefix = \"2001:db8::/64\"\nhosts = \"2001:db8::1\""
+        ),
+        "hosts"
+    );
+    for key in [
+        "autowire = true",
+        "learning = false",
+        "neighbor_oracle = \"kernel\"",
+        "negative_cache_capacity = 64",
+        "negative_hold_down = 5",
+        "negative_max_hold_down = 60",
+    ] {
+        assert_eq!(
+            rejected_key(&format!(
+                "type = \"static\"\nproxied_prefix = \"2001:db8::/64\"\n{}",
+                key
+            )),
+            key.split(' ').next().unwrap()
+        );
+    }
+    assert_eq!(
+        rejected_key(
+            "type = \"static\"\nproxied_prefix = { from_iface = \"wan0\", length = 64 }\nhosts_file = \"hosts\""
+        ),
+        "hosts_file"
+    );
+    assert_eq!(
+        rejected_key(
+            "type = \"forward\"\nproxied_prefix = \"2001:db8::/64\"\nleases = { file = \"leases\", format = \"udhcpd\" }"
+        ),
+        "leases.format"
+    );
+    assert_eq!(
+        rejected_key(
+            "type = \"forward\"\nproxied_prefix = \"2001:db8::/64\"\nleases = { format = \"kea\" }"
+        ),
+        "leases.file"
+    );
+    assert_eq!(
+        rejected_key(
+            "type = \"static\"\nproxied_prefix = { from_iface = \"wan0\", length = 64 }\nleases = { file = \"leases\", format = \"isc\" }"
+        ),
+        "leases"
+    );
+    assert_eq!(rejected_key("proxied_prefix = \"2001:db8::/64\""), "type");
+    assert_eq!(
+        rejected_key("type = \"statik\"\nproxied_prefix = \"2001:db8::/64\""),
+        "type"
+    );
+    assert_eq!(rejected_key("type = \"static\""), "proxied_prefix");
+    assert_eq!(
+        rejected_key("type = \"static\"\nproxied_prefix = \"ff02::/16\""),
+        "proxied_prefix"
+    );
+    assert_eq!(
+        rejected_key("type = \"static\"\nproxied_prefix = \"fe80::/64\""),
+        "proxied_prefix"
+    );
+    assert_eq!(
+        rejected_key("type = \"static\"\nproxied_prefix = { from_iface = \"wan0\", length = 129 }"),
+        "proxied_prefix.length"
+    );
+    assert_eq!(
+        rejected_key(
+            "type = \"static\"\nproxied_prefix = \"2001:db8::/64\"\nproxied_ifaces = [ \"eth0\", { name = \"eth1\" } ]"
+        ),
+        "proxied_ifaces"
+    );
+    assert_eq!(
+        rejected_key(
+            "type = \"static\"\nproxied_prefix = \"2001:db8::/64\"\nrewrite_method = \"nat\"\nlocal_prefix = \"2001:db9::/64\""
+        ),
+        "rewrite_method"
+    );
+    assert_eq!(
+        rejected_key(
+            "type = \"static\"\nproxied_prefix = \"2001:db8::/64\"\nrewrite_method = \"netmap\""
+        ),
+        "local_prefix"
+    );
+    assert_eq!(
+        rejected_key(
+            "type = \"static\"\nproxied_prefix = \"2001:db8::/64\"\nrewrite_method = \"netmap\"\nlocal_prefix = \"2001:db9::/56\""
+        ),
+        "local_prefix"
+    );
+    assert_eq!(
+        rejected_key(
+            "type = \"static\"\nproxied_prefix = \"2001:db8::/60\"\nrewrite_method = \"npt\"\nlocal_prefix = \"2001:db9::/60\""
+        ),
+        "local_prefix"
+    );
+    assert_eq!(
+        rejected_key(
+            "type = \"forward\"\nproxied_prefix = \"2001:db8::/64\"\nneighbor_oracle = \"ndp\""
+        ),
+        "neighbor_oracle"
+    );
+    assert_eq!(
+        rejected_key("type = \"static\"\nproxied_prefix = \"2001:db8::/64\"\ndad = \"forward\""),
+        "dad"
+    );
+    assert_eq!(
+        rejected_key("type = \"forward\"\nproxied_prefix = \"2001:db8::/64\"\ndad = \"ignore\""),
+        "dad"
+    );
+    assert_eq!(
+        rejected_key("type = \"static\"\nproxied_prefix = \"2001:db8::/64\"\nna_override = true"),
+        "na_override"
+    );
+    assert_eq!(
+        rejected_key("type = \"static\"\nproxied_prefix = \"2001:db8::/64\"\nna_jitter = 60000"),
+        "na_jitter"
+    );
+    assert_eq!(
+        rejected_key(
+            "type = \"static\"\nproxied_prefix = \"2001:db8::/64\"\nna_delay = 500\nna_jitter = 501"
+        ),
+        "na_jitter"
+    );
+    assert_eq!(
+        rejected_key(
+            "type = \"static\"\nproxied_prefix = \"2001:db8::/64\"\nconflict_hold_time = 86400"
+        ),
+        "conflict_hold_time"
+    );
+    for hwaddr in [
+        "\"neighbor\"",
+        "\"01:00:5e:00:00:01\"",
+        "\"ff:ff:ff:ff:ff:ff\"",
+        "\"00:00:00:00:00:00\"",
+        "\"00:00:5e:00:01\"",
+        "1",
+    ] {
+        assert_eq!(
+            rejected_key(&format!(
+                "type = \"static\"\nproxied_prefix = \"2001:db8::/64\"\nna_hwaddr = {}",
+                hwaddr
+            )),
+            "na_hwaddr"
+        );
+    }
+    assert_eq!(
+        rejected_key("type = \"forward\"\nproxied_prefix = \"2001:db8::/64\"\nautowrie = true"),
+        "autowrie"
+    );
+    assert_eq!(
+        rejected_key("type = \"forward\"\nproxied_prefix = \"2001:db8::/64\"\nqueue_depth = 0"),
+        "queue_depth"
+    );
+    assert_eq!(
+        rejected_key("type = \"static\"\nproxied_prefix = \"2001:db8::/64\"\nna_queue_depth = 0"),
+        "na_queue_depth"
+    );
+    assert_eq!(
+        rejected_key(
+            "type = \"forward\"\nproxied_prefix = \"2001:db8::/64\"\nnegative_cache_capacity = 0"
+        ),
+        "negative_cache_capacity"
+    );
+    assert_eq!(
+        rejected_key(
+            "type = \"forward\"\nproxied_prefix = \"2001:db8::/64\"\nnegative_hold_down = 0"
+        ),
+        "negative_hold_down"
+    );
+    assert_eq!(
+        rejected_key(
+            "type = \"forward\"\nproxied_prefix = \"2001:db8::/64\"\nnegative_hold_down = 60\nnegative_max_hold_down = 30"
+        ),
+        "negative_hold_down"
+    );
+
+    // [global]
+    let parse_global = |global: &str| {
+        GlobalConfig::new(Some(
+            config::Config::builder()
+                .add_source(config::File::from_str(global, config::FileFormat::Toml))
+                .build()
+                .unwrap()
+                .cache
+                .into_table()
+                .unwrap()
+                .remove("global")
+                .unwrap(),
+        ))
+    };
+    for (global, rejected) in [
+        ("[global]\nrecv_buffer_size = 512", "recv_buffer_size"),
+        ("[global]\nretrans_timer = 0", "retrans_timer"),
+        ("[global]\nlog_level = \"loud\"", "log_level"),
+        ("[global]\nlearning_rate = 0", "learning_rate"),
+        ("[global]\nrefresh_jitter = 7200", "refresh_jitter"),
+        ("[global]\nhop_limit = 64", "hop_limit"),
+    ] {
+        assert!(matches!(
+            parse_global(global),
+            Err(Error::InvalidConfig { section, key, .. }) if section == "global" && key == rejected
+        ));
+    }
+}
+
+#[test]
+fn test_config_overlaps() {
+    let parse = |rules: &str| {
+        parse_rules(
+            "test.toml",
+            &config::Config::builder()
+                .add_source(config::File::from_str(rules, config::FileFormat::Toml))
+                .build()
+                .unwrap(),
+            &GlobalConfig::default(),
+        )
+    };
+    let names = |configs: Vec<NDConfig>| -> Vec<String> {
+        configs.into_iter().map(|conf| conf.name).collect()
+    };
+
+    let same = "[ndp.a]\ntype = \"static\"\nproxied_prefix = \"2001:db8::/64\"\n\
+                [ndp.b]\ntype = \"static\"\nproxied_prefix = \"2001:db8::1/64\"\n";
+    assert!(matches!(
+        parse(same),
+        Err(Error::InvalidConfig { section, key, .. }) if section == "ndp.b" && key == "proxied_prefix"
+    ));
+    assert_eq!(
+        names(parse(&format!("{}override = true\n", same)).unwrap()),
+        vec!["b"]
+    );
+    let nested = |outer_first: bool, outer_override: bool, inner_override: bool| {
+        let rule = |name, prefix, overrides: bool| {
+            format!(
+                "[ndp.{}]\ntype = \"static\"\nproxied_prefix = \"{}\"\noverride = {}\n",
+                name, prefix, overrides
+            )
+        };
+        let (outer, inner) = (
+            rule("outer", "2001:db8::/48", outer_override),
+            rule("inner", "2001:db8::/64", inner_override),
+        );
+        match outer_first {
+            true => parse(&(outer + &inner)),
+            false => parse(&(inner + &outer)),
+        }
+    };
+    for outer_first in [true, false] {
+        // the inner one wins, with or without override on it
+        for inner_override in [false, true] {
+            let mut started = names(nested(outer_first, false, inner_override).unwrap());
+            started.sort();
+            assert_eq!(started, vec!["inner", "outer"]);
+        }
+        // an override on the outer one could not take effect
+        assert!(matches!(
+            nested(outer_first, true, false),
+            Err(Error::InvalidConfig { section, key, .. }) if section == "ndp.outer" && key == "override"
+        ));
+        assert!(nested(outer_first, true, true).is_ok());
+    }
+}
+
+#[test]
+fn test_config_includes() {
+    let (_, configs) = parse_config("test/test5.toml").unwrap();
+    assert_eq!(
+        configs
+            .iter()
+            .map(|conf| (
+                conf.get_name().as_str(),
+                conf.get_source().to_str().unwrap()
+            ))
+            .collect::<Vec<_>>(),
+        vec![
+            ("conf5", "test/test5.toml"),
+            ("conf5a", "test/test5.d/10-a.toml"),
+            ("conf5b", "test/test5.d/20-b.toml"),
+        ]
+    );
+    assert!(matches!(
+        parse_config("test/test6.toml"),
+        Err(Error::InvalidConfig { file, section, .. })
+            if file == "test/test5.d/10-a.toml" && section == "ndp.conf5a"
+    ));
 }
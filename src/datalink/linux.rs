@@ -53,23 +53,18 @@ impl PacketReceiverOpts for PacketReceiver {
     fn set_filter_pass_ipv6_ns(&self) -> Result<(), Error> {
         let ipv6_ns_filter = [
             // offsetof(ipv6 header, ipv6 next header)
-            BPFFilter::bpf_stmt((BPF_LD | BPF_B | BPF_ABS) as u16, 6),
-            BPFFilter::bpf_jump(
-                (BPF_JMP | BPF_JEQ | BPF_K) as u16,
-                libc::IPPROTO_ICMPV6 as u32,
-                0,
-                3,
-            ),
+            BPFFilter::bpf_stmt(BPF_LD | BPF_B | BPF_ABS, 6),
+            BPFFilter::bpf_jump(BPF_JMP | BPF_JEQ | BPF_K, libc::IPPROTO_ICMPV6 as u32, 0, 3),
             // sizeof(ipv6 header) + offsetof(icmpv6 header, icmp6_type)
-            BPFFilter::bpf_stmt((BPF_LD | BPF_B | BPF_ABS) as u16, 40 + 0),
+            BPFFilter::bpf_stmt(BPF_LD | BPF_B | BPF_ABS, 40),
             BPFFilter::bpf_jump(
-                (BPF_JMP | BPF_JEQ | BPF_K) as u16,
+                BPF_JMP | BPF_JEQ | BPF_K,
                 Icmpv6Types::NeighborSolicit.0 as u32,
                 0,
                 1,
             ),
-            BPFFilter::bpf_stmt((BPF_RET | BPF_K) as u16, u32::MAX),
-            BPFFilter::bpf_stmt((BPF_RET | BPF_K) as u16, 0),
+            BPFFilter::bpf_stmt(BPF_RET | BPF_K, u32::MAX),
+            BPFFilter::bpf_stmt(BPF_RET | BPF_K, 0),
         ];
         let ipv6_socket_fprog = BPFFProg::new(&ipv6_ns_filter);
 
@@ -80,24 +75,30 @@ impl PacketReceiverOpts for PacketReceiver {
 
     fn set_filter_pass_ipv6_na(&self) -> Result<(), Error> {
         let ipv6_na_filter = [
-            // offsetof(ipv6 header, ipv6 next header)
-            BPFFilter::bpf_stmt((BPF_LD | BPF_B | BPF_ABS) as u16, 6),
+            // the NAs sent by the host are not from neighbors
+            BPFFilter::bpf_stmt(
+                BPF_LD | BPF_B | BPF_ABS,
+                (libc::SKF_AD_OFF + libc::SKF_AD_PKTTYPE) as u32,
+            ),
             BPFFilter::bpf_jump(
-                (BPF_JMP | BPF_JEQ | BPF_K) as u16,
-                libc::IPPROTO_ICMPV6 as u32,
+                BPF_JMP | BPF_JEQ | BPF_K,
+                libc::PACKET_OUTGOING as u32,
+                5,
                 0,
-                3,
             ),
+            // offsetof(ipv6 header, ipv6 next header)
+            BPFFilter::bpf_stmt(BPF_LD | BPF_B | BPF_ABS, 6),
+            BPFFilter::bpf_jump(BPF_JMP | BPF_JEQ | BPF_K, libc::IPPROTO_ICMPV6 as u32, 0, 3),
             // sizeof(ipv6 header) + offsetof(icmpv6 header, icmp6_type)
-            BPFFilter::bpf_stmt((BPF_LD | BPF_B | BPF_ABS) as u16, 40 + 0),
+            BPFFilter::bpf_stmt(BPF_LD | BPF_B | BPF_ABS, 40),
             BPFFilter::bpf_jump(
-                (BPF_JMP | BPF_JEQ | BPF_K) as u16,
+                BPF_JMP | BPF_JEQ | BPF_K,
                 Icmpv6Types::NeighborAdvert.0 as u32,
                 0,
                 1,
             ),
-            BPFFilter::bpf_stmt((BPF_RET | BPF_K) as u16, u32::MAX),
-            BPFFilter::bpf_stmt((BPF_RET | BPF_K) as u16, 0),
+            BPFFilter::bpf_stmt(BPF_RET | BPF_K, u32::MAX),
+            BPFFilter::bpf_stmt(BPF_RET | BPF_K, 0),
         ];
         let ipv6_socket_fprog = BPFFProg::new(&ipv6_na_filter);
 
@@ -105,6 +106,39 @@ impl PacketReceiverOpts for PacketReceiver {
             .attach_filter(self.socket.as_raw_fd())
             .map_err(|_| Error::SocketOpt(SocketOptTypes::AttachBPF))
     }
+
+    fn set_filter_pass_ipv6_global_src(&self) -> Result<(), Error> {
+        let ipv6_global_src_filter = [
+            // the packets sent by the host are not from neighbors
+            BPFFilter::bpf_stmt(
+                BPF_LD | BPF_B | BPF_ABS,
+                (libc::SKF_AD_OFF + libc::SKF_AD_PKTTYPE) as u32,
+            ),
+            BPFFilter::bpf_jump(
+                BPF_JMP | BPF_JEQ | BPF_K,
+                libc::PACKET_OUTGOING as u32,
+                8,
+                0,
+            ),
+            // offsetof(ipv6 header, ipv6 source address),
+            // neither unspecified, loopback, multicast nor link-local (fe80::/10)
+            BPFFilter::bpf_stmt(BPF_LD | BPF_B | BPF_ABS, 8),
+            BPFFilter::bpf_jump(BPF_JMP | BPF_JEQ | BPF_K, 0x00, 6, 0),
+            BPFFilter::bpf_jump(BPF_JMP | BPF_JEQ | BPF_K, 0xff, 5, 0),
+            BPFFilter::bpf_jump(BPF_JMP | BPF_JEQ | BPF_K, 0xfe, 0, 3),
+            BPFFilter::bpf_stmt(BPF_LD | BPF_B | BPF_ABS, 9),
+            BPFFilter::bpf_stmt(BPF_ALU | BPF_AND | BPF_K, 0xc0),
+            BPFFilter::bpf_jump(BPF_JMP | BPF_JEQ | BPF_K, 0x80, 1, 0),
+            // up to the end of ipv6 source address
+            BPFFilter::bpf_stmt(BPF_RET | BPF_K, 24),
+            BPFFilter::bpf_stmt(BPF_RET | BPF_K, 0),
+        ];
+        let ipv6_socket_fprog = BPFFProg::new(&ipv6_global_src_filter);
+
+        ipv6_socket_fprog
+            .attach_filter(self.socket.as_raw_fd())
+            .map_err(|_| Error::SocketOpt(SocketOptTypes::AttachBPF))
+    }
 }
 
 impl PacketSenderOpts for PacketSender {
@@ -3,6 +3,7 @@ mod linux;
 
 use crate::error::Error;
 use crate::interfaces;
+use pnet::util::MacAddr;
 use socket2::{Domain, Protocol, SockAddr, Socket, Type};
 use tokio::io::unix::AsyncFd;
 
@@ -19,6 +20,8 @@ pub trait PacketReceiverOpts {
     /// for Unix-like systems, crate classic_bpf is used
     fn set_filter_pass_ipv6_ns(&self) -> Result<(), Error>;
     fn set_filter_pass_ipv6_na(&self) -> Result<(), Error>;
+    /// copy only the IPv6 headers of the received packets from global addresses
+    fn set_filter_pass_ipv6_global_src(&self) -> Result<(), Error>;
 }
 
 pub struct PacketReceiver {
@@ -27,10 +30,11 @@ pub struct PacketReceiver {
 }
 
 impl PacketReceiver {
-    pub fn new() -> Result<Self, Error> {
+    /// packets longer than buf_size are truncated
+    pub fn new(buf_size: usize) -> Result<Self, Error> {
         let inner = Socket::new(Domain::PACKET, Type::DGRAM, Some(Protocol::ICMPV6))?;
         inner.set_nonblocking(true)?;
-        let buf = vec![MaybeUninit::<u8>::zeroed(); 1500];
+        let buf = vec![MaybeUninit::<u8>::zeroed(); buf_size];
         Ok(Self {
             socket: AsyncFd::new(inner)?,
             buf,
@@ -57,6 +61,35 @@ impl PacketReceiver {
             }
         }
     }
+
+    /// receive a packet along with the link-layer address of its sender
+    pub async fn recv_pkt_from(&mut self) -> Result<(Vec<u8>, MacAddr), Error> {
+        loop {
+            match self
+                .socket
+                .readable()
+                .await?
+                .try_io(|socket| socket.get_ref().recv_from(&mut self.buf))
+            {
+                Ok(received) => {
+                    let (len, addr) = received?;
+                    // the socket is of AF_PACKET
+                    let addr = unsafe { *(addr.as_ptr() as *const libc::sockaddr_ll) };
+                    let hwaddr = addr.sll_addr;
+                    return Ok((
+                        self.buf[0..len]
+                            .iter()
+                            .map(|x| unsafe { x.assume_init() })
+                            .collect(),
+                        MacAddr::new(
+                            hwaddr[0], hwaddr[1], hwaddr[2], hwaddr[3], hwaddr[4], hwaddr[5],
+                        ),
+                    ));
+                }
+                Err(_) => continue,
+            }
+        }
+    }
 }
 
 pub trait PacketSenderOpts {
@@ -1,7 +1,8 @@
-use crate::conf::TTL_OF_CACHE;
+use crate::conf::{self, GlobalConfig};
 use crate::error::Error;
 use crate::interfaces::{self, NDInterface};
 use crate::na_monitor::NAMonitor;
+use crate::neighbors::NeighborTable;
 use r_cache::cache::Cache;
 use std::sync::Arc;
 
@@ -12,7 +13,18 @@ pub async fn namonitor(iface_names: &[String]) -> Result<(), Error> {
         .collect();
     let iface: NDInterface = tmp[0].clone();
     //
-    let neighbors_cache = Arc::new(Cache::new(Some(TTL_OF_CACHE)));
+    let global = GlobalConfig::default();
+    let neighbors_cache = Arc::new(NeighborTable::new(&global));
+    let pending_solicitations = Arc::new(Cache::new(Some(global.pending_ns_ttl())));
     //
-    NAMonitor::new(iface, neighbors_cache)?.run().await
+    NAMonitor::new(
+        iface,
+        neighbors_cache,
+        pending_solicitations,
+        Arc::new(Cache::new(Some(conf::OBSERVED_NA_TTL))),
+        true,
+        global.get_recv_buffer_size(),
+    )?
+    .run()
+    .await
 }
@@ -37,7 +37,7 @@ pub async fn send_na_to(iface_names: &[String], proxied_na_addr: Ipv6Addr) -> Re
         iface.get_link_addr(),
         &dst_addr,
         &proxied_na_addr,
-        iface.get_hwaddr(),
+        Some(iface.get_hwaddr()),
         0,
     )
     .unwrap();
@@ -1,3 +1,4 @@
+use crate::conf::GlobalConfig;
 use crate::dev::recv_handler::mpsc_recv_and_drop;
 use crate::error::Error;
 use crate::interfaces;
@@ -5,7 +6,7 @@ use crate::ns_monitor::NSMonitor;
 use crate::routing::construst_routing_table;
 use futures::future::{FutureExt, select, select_all};
 use ipnet::Ipv6Net;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, watch};
 
 pub async fn nsmonitor(iface_names: &[String]) -> Result<(), Error> {
     //
@@ -19,7 +20,13 @@ pub async fn nsmonitor(iface_names: &[String]) -> Result<(), Error> {
     // prepare monitors for Neighbor Solicitations
     let nsmonitors: Vec<_> = monitored_ifaces
         .into_values()
-        .map(|iface| NSMonitor::new(construst_routing_table(route_map.clone()), iface))
+        .map(|iface| {
+            NSMonitor::new(
+                watch::channel(construst_routing_table(route_map.clone())).1,
+                iface,
+                GlobalConfig::default().get_recv_buffer_size(),
+            )
+        })
         .map(|inst| inst.unwrap().run().boxed())
         .collect();
 
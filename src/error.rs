@@ -8,8 +8,15 @@ pub enum Error {
     IPNet(#[from] ipnet::AddrParseError),
     #[error("config error")]
     Config(#[from] config::ConfigError),
-    #[error("tokio mpsc send error")]
-    Mpsc(#[from] tokio::sync::mpsc::error::SendError<SharedNSPacket>),
+    #[error("invalid config{}", describe_invalid_config(.file, .section, .key, .reason))]
+    InvalidConfig {
+        /// empty if unknown
+        file: String,
+        /// empty for the top level
+        section: String,
+        key: String,
+        reason: String,
+    },
     #[error("tokio mpsc recv error")]
     MpscRecvNone(),
     #[error("std io errors")]
@@ -18,6 +25,49 @@ pub enum Error {
     SocketOpt(SocketOptTypes),
     #[error("NA/NS packet generation error")]
     PacketGeneration(NDTypes),
+    #[error("rtnetlink error, errno {0}")]
+    Netlink(i32),
     #[error("tokio join error")]
     JoinErrorTokio(#[from] JoinError),
 }
+
+impl Error {
+    /// tell which file the invalid config comes from
+    pub fn in_file(self, path: &str) -> Self {
+        match self {
+            Error::InvalidConfig {
+                file,
+                section,
+                key,
+                reason,
+            } if file.is_empty() => Error::InvalidConfig {
+                file: path.to_string(),
+                section,
+                key,
+                reason,
+            },
+            // the file could not be read or parsed at all
+            Error::Config(e) => Error::InvalidConfig {
+                file: path.to_string(),
+                section: String::new(),
+                key: String::new(),
+                reason: e.to_string(),
+            },
+            e => e,
+        }
+    }
+}
+
+fn describe_invalid_config(file: &str, section: &str, key: &str, reason: &str) -> String {
+    let mut ret = String::new();
+    if !file.is_empty() {
+        ret.push_str(&format!(" in {}", file));
+    }
+    if !section.is_empty() {
+        ret.push_str(&format!(" [{}]", section));
+    }
+    match key.is_empty() {
+        true => format!("{}: {}", ret, reason),
+        false => format!("{}, {}: {}", ret, key, reason),
+    }
+}
@@ -0,0 +1,109 @@
+use crate::error::Error;
+use std::ffi::CString;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::unix::AsyncFd;
+
+/// sizeof(struct inotify_event), without the name
+const INOTIFY_EVENT_LEN: usize = 16;
+/// the file is written, replaced, or removed.
+/// IN_MODIFY is for the daemons keeping their files open, like the DHCPv6 servers
+const INOTIFY_MASK: u32 = libc::IN_MODIFY
+    | libc::IN_CLOSE_WRITE
+    | libc::IN_MOVED_TO
+    | libc::IN_DELETE
+    | libc::IN_MOVED_FROM;
+/// a write usually comes with more, wait for them before reading the file
+const SETTLE_TIME: Duration = Duration::from_millis(100);
+
+/// follows the changes of a file with inotify,
+/// the directory is watched, so that the file can be created later, or replaced by rename
+pub struct FileMonitor {
+    path: PathBuf,
+    inotify: AsyncFd<OwnedFd>,
+    buf: Vec<u8>,
+}
+
+impl FileMonitor {
+    pub fn new(path: &Path) -> Result<Self, Error> {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        let inotify = unsafe { OwnedFd::from_raw_fd(fd) };
+        let dir_cstr = CString::new(dir.as_os_str().as_bytes())
+            .map_err(|e| Error::Io(std::io::Error::other(e)))?;
+        if unsafe { libc::inotify_add_watch(fd, dir_cstr.as_ptr(), INOTIFY_MASK) } < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            inotify: AsyncFd::new(inotify)?,
+            buf: vec![0u8; 4096],
+        })
+    }
+
+    pub fn get_path(&self) -> &Path {
+        &self.path
+    }
+
+    /// wait until the file has changed, and settled
+    pub async fn changed(&mut self) -> Result<(), Error> {
+        while !self.wait_events().await? {}
+        tokio::time::sleep(SETTLE_TIME).await;
+        // the events during SETTLE_TIME are covered
+        while read_events(&self.inotify, &mut self.buf).is_ok_and(|len| len > 0) {}
+        Ok(())
+    }
+
+    /// whether any of the events is about the file
+    async fn wait_events(&mut self) -> Result<bool, Error> {
+        let len = match self
+            .inotify
+            .readable()
+            .await?
+            .try_io(|inotify| read_events(inotify.get_ref(), &mut self.buf))
+        {
+            Ok(len) => len?,
+            Err(_) => return Ok(false),
+        };
+        let name = self.path.file_name().unwrap_or_default().as_bytes();
+        let mut events = &self.buf[..len];
+        let mut changed = false;
+        while events.len() >= INOTIFY_EVENT_LEN {
+            let mask = u32::from_ne_bytes(events[4..8].try_into().unwrap());
+            let name_len = u32::from_ne_bytes(events[12..16].try_into().unwrap()) as usize;
+            let end = (INOTIFY_EVENT_LEN + name_len).min(events.len());
+            let event_name = &events[INOTIFY_EVENT_LEN..end];
+            // the name is padded with NULs
+            let event_name = &event_name[..event_name
+                .iter()
+                .position(|c| *c == 0)
+                .unwrap_or(event_name.len())];
+            // some events are lost, the file may have changed as well
+            changed |= mask & libc::IN_Q_OVERFLOW != 0 || event_name == name;
+            events = &events[end..];
+        }
+        Ok(changed)
+    }
+}
+
+/// read the events without blocking
+fn read_events(inotify: &impl AsRawFd, buf: &mut [u8]) -> std::io::Result<usize> {
+    match unsafe {
+        libc::read(
+            inotify.as_raw_fd(),
+            buf.as_mut_ptr() as *mut libc::c_void,
+            buf.len(),
+        )
+    } {
+        n if n < 0 => Err(std::io::Error::last_os_error()),
+        n => Ok(n as usize),
+    }
+}
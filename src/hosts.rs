@@ -0,0 +1,244 @@
+use ip_network_table_deps_treebitmap::IpLookupTable;
+use ipnet::Ipv6Net;
+use std::collections::HashSet;
+use std::net::Ipv6Addr;
+use std::path::Path;
+
+/// parse an entry of hosts into prefixes:
+///     "2001:db8::1" is a host
+///     "2001:db8::100-2001:db8::1ff" is a range of addresses, both ends included
+///     "2001:db8::1:0/112" is a sub-prefix
+pub fn parse_hosts(entry: &str) -> Result<Vec<Ipv6Net>, String> {
+    let parse_addr = |s: &str| {
+        s.trim()
+            .parse::<Ipv6Addr>()
+            .map_err(|e| format!("invalid address {}: {}", s.trim(), e))
+    };
+    if let Some((start, end)) = entry.split_once('-') {
+        let (start, end) = (parse_addr(start)?, parse_addr(end)?);
+        if start > end {
+            return Err(format!("{} is after {}", start, end));
+        }
+        Ok(range_to_prefixes(start.into(), end.into()))
+    } else if entry.contains('/') {
+        entry
+            .trim()
+            .parse::<Ipv6Net>()
+            .map(|net| vec![net.trunc()])
+            .map_err(|e| format!("invalid prefix {}: {}", entry.trim(), e))
+    } else {
+        Ok(vec![Ipv6Net::from(parse_addr(entry)?)])
+    }
+}
+
+/// the fewest prefixes covering the range from start to end
+fn range_to_prefixes(mut start: u128, end: u128) -> Vec<Ipv6Net> {
+    let mut ret = Vec::new();
+    loop {
+        // the largest block aligned to start that does not go past end
+        let mut bits = start.trailing_zeros();
+        while bits > 0 && u128::MAX >> (128 - bits) > end - start {
+            bits -= 1;
+        }
+        ret.push(Ipv6Net::new(Ipv6Addr::from(start), (128 - bits) as u8).unwrap());
+        let last = match bits {
+            0 => start,
+            _ => start | u128::MAX >> (128 - bits),
+        };
+        if last >= end {
+            return ret;
+        }
+        start = last + 1;
+    }
+}
+
+/// parse a file of hosts, one entry of parse_hosts() per line,
+/// empty lines and the ones starting with # are ignored
+pub fn parse_hosts_file(content: &str, proxied_pfx: &Ipv6Net) -> Result<Vec<Ipv6Net>, String> {
+    let mut ret = Vec::new();
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        for prefix in parse_hosts(line).map_err(|e| format!("line {}: {}", i + 1, e))? {
+            if !proxied_pfx.contains(&prefix) {
+                return Err(format!(
+                    "line {}: {} is out of {}",
+                    i + 1,
+                    line,
+                    proxied_pfx
+                ));
+            }
+            ret.push(prefix);
+        }
+    }
+    Ok(ret)
+}
+
+/// read and parse a file of hosts
+pub fn load_hosts_file(path: &Path, proxied_pfx: &Ipv6Net) -> Result<Vec<Ipv6Net>, String> {
+    let content = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    parse_hosts_file(&content, proxied_pfx)
+}
+
+/// swap the hosts for the ones of the config and the file, and tell how many prefixes the file has,
+/// the current hosts are kept if the file cannot be loaded
+pub fn reload_hosts_file(
+    static_hosts: &mut Option<StaticHosts>,
+    config_hosts: &[Ipv6Net],
+    path: &Path,
+    proxied_pfx: &Ipv6Net,
+) -> Result<usize, String> {
+    let file_hosts = load_hosts_file(path, proxied_pfx)?;
+    *static_hosts = Some(StaticHosts::new(&[config_hosts, &file_hosts].concat()));
+    Ok(file_hosts.len())
+}
+
+/// the targets that a static rule answers for
+pub struct StaticHosts {
+    table: IpLookupTable<Ipv6Addr, ()>,
+    /// the /128 ones, which are offloaded for good
+    hosts: HashSet<Ipv6Addr>,
+}
+
+impl StaticHosts {
+    pub fn new(prefixes: &[Ipv6Net]) -> Self {
+        let mut table = IpLookupTable::new();
+        for prefix in prefixes {
+            table.insert(prefix.addr(), prefix.prefix_len() as u32, ());
+        }
+        Self {
+            table,
+            hosts: prefixes
+                .iter()
+                .filter(|prefix| prefix.prefix_len() == 128)
+                .map(|prefix| prefix.addr())
+                .collect(),
+        }
+    }
+
+    pub fn contains(&self, addr: Ipv6Addr) -> bool {
+        self.table.longest_match(addr).is_some()
+    }
+
+    /// whether the address is a single host, rather than one in a sub-prefix or range
+    pub fn is_host(&self, addr: Ipv6Addr) -> bool {
+        self.hosts.contains(&addr)
+    }
+
+    pub fn hosts(&self) -> &HashSet<Ipv6Addr> {
+        &self.hosts
+    }
+}
+
+#[test]
+fn test_static_hosts() {
+    let prefixes = |v: &[&str]| -> Vec<Ipv6Net> { v.iter().map(|s| s.parse().unwrap()).collect() };
+    assert_eq!(
+        parse_hosts("2001:db8::1").unwrap(),
+        prefixes(&["2001:db8::1/128"])
+    );
+    assert_eq!(
+        parse_hosts("2001:db8::1:1/112").unwrap(),
+        prefixes(&["2001:db8::1:0/112"])
+    );
+    assert_eq!(
+        parse_hosts("2001:db8::100-2001:db8::1ff").unwrap(),
+        prefixes(&["2001:db8::100/120"])
+    );
+    assert_eq!(
+        parse_hosts("2001:db8::ff - 2001:db8::202").unwrap(),
+        prefixes(&[
+            "2001:db8::ff/128",
+            "2001:db8::100/120",
+            "2001:db8::200/127",
+            "2001:db8::202/128"
+        ])
+    );
+    assert_eq!(
+        parse_hosts(":: - ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff").unwrap(),
+        prefixes(&["::/0"])
+    );
+    assert!(parse_hosts("2001:db8::2-2001:db8::1").is_err());
+    assert!(parse_hosts("2001:db8::x").is_err());
+    assert!(parse_hosts("2001:db8::/129").is_err());
+
+    let pfx: Ipv6Net = "2001:db8::/64".parse().unwrap();
+    assert_eq!(
+        parse_hosts_file(
+            "# vm1\n2001:db8::1\n\n  2001:db8::100-2001:db8::1ff  \n2001:db8::1:0/112\n",
+            &pfx
+        )
+        .unwrap(),
+        prefixes(&["2001:db8::1/128", "2001:db8::100/120", "2001:db8::1:0/112"])
+    );
+    assert_eq!(parse_hosts_file("", &pfx).unwrap(), vec![]);
+    assert_eq!(
+        parse_hosts_file("2001:db8::1\nzzz\n", &pfx),
+        Err(String::from(
+            "line 2: invalid address zzz: invalid IPv6 address syntax"
+        ))
+    );
+    assert!(parse_hosts_file("2001:db9::1\n", &pfx).is_err());
+
+    let hosts = StaticHosts::new(
+        &[
+            parse_hosts("2001:db8::1").unwrap(),
+            parse_hosts("2001:db8::ff-2001:db8::202").unwrap(),
+        ]
+        .concat(),
+    );
+    assert_eq!(
+        hosts.hosts(),
+        &HashSet::from(
+            ["2001:db8::1", "2001:db8::ff", "2001:db8::202"].map(|s| s.parse().unwrap())
+        )
+    );
+    for (addr, contained, host) in [
+        ("2001:db8::1", true, true),
+        ("2001:db8::2", false, false),
+        ("2001:db8::fe", false, false),
+        ("2001:db8::ff", true, true),
+        ("2001:db8::180", true, false),
+        ("2001:db8::202", true, true),
+        ("2001:db8::203", false, false),
+    ] {
+        assert_eq!(hosts.contains(addr.parse().unwrap()), contained, "{}", addr);
+        assert_eq!(hosts.is_host(addr.parse().unwrap()), host, "{}", addr);
+    }
+}
+
+#[test]
+fn test_reload_hosts_file() {
+    let prefix: Ipv6Net = "2001:db8::/64".parse().unwrap();
+    let config_hosts = ["2001:db8::1/128".parse().unwrap()];
+    let path = std::env::temp_dir().join(format!("ndproxy-hosts-{}", std::process::id()));
+    let addr = |s: &str| -> Ipv6Addr { s.parse().unwrap() };
+    let mut static_hosts = None;
+
+    std::fs::write(
+        &path,
+        "2001:db8::10\n# a range\n2001:db8::100-2001:db8::1ff\n",
+    )
+    .unwrap();
+    assert_eq!(
+        reload_hosts_file(&mut static_hosts, &config_hosts, &path, &prefix),
+        Ok(2)
+    );
+    let hosts = static_hosts.as_ref().unwrap();
+    assert!(hosts.is_host(addr("2001:db8::1")) && hosts.is_host(addr("2001:db8::10")));
+    assert!(hosts.contains(addr("2001:db8::180")));
+
+    // a broken file keeps the hosts loaded before
+    std::fs::write(&path, "2001:db8::20\nnot an address\n").unwrap();
+    assert!(reload_hosts_file(&mut static_hosts, &config_hosts, &path, &prefix).is_err());
+    let hosts = static_hosts.as_ref().unwrap();
+    assert!(hosts.is_host(addr("2001:db8::10")) && !hosts.contains(addr("2001:db8::20")));
+    assert!(hosts.contains(addr("2001:db8::180")));
+
+    // so does a file that has gone
+    std::fs::remove_file(&path).unwrap();
+    assert!(reload_hosts_file(&mut static_hosts, &config_hosts, &path, &prefix).is_err());
+    assert!(static_hosts.as_ref().unwrap().is_host(addr("2001:db8::10")));
+}
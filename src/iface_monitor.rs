@@ -0,0 +1,474 @@
+use crate::error::Error;
+use crate::interfaces::{self, IfaceSelector, NDInterface};
+use crate::learning_monitor::LearningMonitor;
+use crate::na_monitor::NAMonitor;
+use crate::netlink::{self, NetlinkSocket};
+use crate::ns_monitor::NSMonitor;
+use crate::routing::construst_routing_table;
+use crate::types::*;
+use ipnet::Ipv6Net;
+use log::{error, info, warn};
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+
+/// a failed rescan is retried after RESCAN_BACKOFF, doubled on every failure up to RESCAN_MAX_BACKOFF
+const RESCAN_BACKOFF: Duration = Duration::from_secs(1);
+const RESCAN_MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// the interfaces are rescanned this often anyway, so that the monitors that have exited,
+/// or failed to start, are started again
+const RESCAN_INTERVAL: Duration = Duration::from_secs(10);
+
+/// the interfaces and the prefix a NDProxy is interested in
+pub struct ProxySubscriber {
+    pub proxied_ifaces: IfaceSelector,
+    pub forwarded_ifaces: IfaceSelector,
+    /// learn the neighbors on forwarded_ifaces from their traffic
+    pub learning: bool,
+    /// watch proxied_ifaces for the NAs of others, for delaying NAs or detecting conflicts
+    pub observe_nas: bool,
+    pub ifaces_sender: ProxyIfacesSender,
+    /// None until the prefix is known, for the ones following the address of an interface
+    pub proxied_prefix: Option<Ipv6Net>,
+    /// the prefix that the targets are rewritten into, None if it is the proxied prefix
+    pub local_prefix: Option<Ipv6Net>,
+    /// (interface, prefix length) that the proxied prefix is taken from
+    pub prefix_source: Option<(String, u8)>,
+    pub prefix_sender: ProxiedPrefixSender,
+    /// where NSMonitors send the NSes for the proxied prefix
+    pub ns_sender: SharedNSPacketSender,
+}
+
+/// changes of the NDProxies, on reloading the config
+pub enum ProxyControl {
+    /// follow the interfaces and the prefix of a new NDProxy, replacing the old one of the same name
+    Add(String, ProxySubscriber),
+    /// the NDProxy has stopped
+    Remove(String),
+    /// the interfaces of the NDProxy in config have changed, (proxied_ifaces, forwarded_ifaces)
+    UpdateIfaces(String, IfaceSelector, IfaceSelector),
+}
+pub type ProxyControlSender = mpsc::UnboundedSender<ProxyControl>;
+type ProxyControlReceiver = mpsc::UnboundedReceiver<ProxyControl>;
+
+/// a NSMonitor, NAMonitor or LearningMonitor running on an interface
+struct MonitorTask {
+    iface: NDInterface,
+    handle: JoinHandle<()>,
+}
+
+/// follows the changes of interfaces via rtnetlink:
+///     0. starts or stops NSMonitors, NAMonitors and LearningMonitors
+///     1. updates the upstream and downstream interfaces of every NDProxy
+///     2. updates the proxied prefixes taken from the addresses of interfaces,
+///        and the routing table of NSMonitors
+pub struct IfaceMonitor {
+    socket: NetlinkSocket,
+    /// for dumping the kinds and the groups of interfaces
+    links: NetlinkSocket,
+    /// interfaces with link-local addresses
+    ifaces: HashMap<u32, NDInterface>,
+    /// keyed by the name of rule
+    proxies: BTreeMap<String, ProxySubscriber>,
+    control_sender: ProxyControlSender,
+    control_receiver: ProxyControlReceiver,
+    /// the routing table shared by NSMonitors
+    routing_table: watch::Sender<NSRoutingTable>,
+    neighbors_cache: NeighborsCache,
+    pending_solicitations: PendingSolicitations,
+    observed_nas: ObservedNAs,
+    /// for the packet receivers of the monitors
+    recv_buffer_size: usize,
+    /// neighbors learned per second by a LearningMonitor
+    learning_rate: u32,
+    ns_monitors: HashMap<u32, MonitorTask>,
+    na_monitors: HashMap<u32, MonitorTask>,
+    /// NAMonitors on the upstream interfaces of the NDProxies delaying NAs or detecting conflicts,
+    /// except for the downstream ones that have a NAMonitor already
+    observer_monitors: HashMap<u32, MonitorTask>,
+    /// on the downstream interfaces of the NDProxies in learning mode
+    learning_monitors: HashMap<u32, MonitorTask>,
+    /// all of the downstream interfaces
+    downstream_sender: watch::Sender<HashMap<u32, NDInterface>>,
+    /// the local prefixes of the NDProxies in learning mode, on their downstream interfaces
+    learning_prefixes: watch::Sender<LearningPrefixes>,
+}
+
+impl IfaceMonitor {
+    pub fn new(
+        neighbors_cache: NeighborsCache,
+        pending_solicitations: PendingSolicitations,
+        observed_nas: ObservedNAs,
+        recv_buffer_size: usize,
+        learning_rate: u32,
+    ) -> Result<Self, Error> {
+        let (control_sender, control_receiver) = mpsc::unbounded_channel();
+        Ok(Self {
+            socket: NetlinkSocket::new((libc::RTMGRP_LINK | libc::RTMGRP_IPV6_IFADDR) as u32)?,
+            links: NetlinkSocket::new(0)?,
+            ifaces: HashMap::new(),
+            proxies: BTreeMap::new(),
+            control_sender,
+            control_receiver,
+            routing_table: watch::channel(construst_routing_table([])).0,
+            neighbors_cache,
+            pending_solicitations,
+            observed_nas,
+            recv_buffer_size,
+            learning_rate,
+            ns_monitors: HashMap::new(),
+            na_monitors: HashMap::new(),
+            observer_monitors: HashMap::new(),
+            learning_monitors: HashMap::new(),
+            downstream_sender: watch::channel(HashMap::new()).0,
+            learning_prefixes: watch::channel(HashMap::new()).0,
+        })
+    }
+
+    /// keep the interfaces and the prefix of a NDProxy up to date
+    pub fn add_proxy(&mut self, name: String, proxy: ProxySubscriber) {
+        self.proxies.insert(name, proxy);
+        self.update_routing_table();
+    }
+
+    /// for changing the NDProxies once I am running
+    pub fn controller(&self) -> ProxyControlSender {
+        self.control_sender.clone()
+    }
+
+    /// follow the changes of downstream interfaces
+    pub fn subscribe_downstream(&self) -> watch::Receiver<HashMap<u32, NDInterface>> {
+        self.downstream_sender.subscribe()
+    }
+
+    /// main loop: apply the interfaces on start, and whenever the kernel notifies me
+    pub async fn run(mut self) -> Result<(), Error> {
+        warn!("IfaceMonitor: Start to work.");
+        // failing to start the monitors on start is fatal, as it was before
+        self.reconcile(true).await?;
+        let mut backoff = RESCAN_BACKOFF;
+        let mut retry_at = None;
+        let mut interval =
+            tokio::time::interval_at((Instant::now() + RESCAN_INTERVAL).into(), RESCAN_INTERVAL);
+        loop {
+            // a retry does not reset the backoff, or a broken socket would be polled every RESCAN_BACKOFF
+            let (received, retrying) = tokio::select! {
+                received = self.socket.recv(), if retry_at.is_none() => match received {
+                    Ok(_) => (Ok(()), false),
+                    // the kernel dropped some notifications, rescan anyway
+                    Err(Error::Io(e)) if e.raw_os_error() == Some(libc::ENOBUFS) => {
+                        warn!("IfaceMonitor: Lost interface notifications.");
+                        (Ok(()), false)
+                    }
+                    Err(e) => (Err(e), false),
+                },
+                // control_receiver never closes, because I am holding control_sender
+                Some(control) = self.control_receiver.recv() => {
+                    self.apply(control);
+                    (Ok(()), false)
+                }
+                () = retry_due(retry_at) => (Ok(()), true),
+                _ = interval.tick() => (Ok(()), retry_at.is_some()),
+            };
+            let result = match received {
+                Ok(()) => self.reconcile(false).await,
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(()) => {
+                    retry_at = None;
+                    if !retrying {
+                        backoff = RESCAN_BACKOFF;
+                    }
+                }
+                Err(e) => {
+                    error!(
+                        "IfaceMonitor: _{:?}_ Failed to follow the interfaces, retry in {:?}.",
+                        e, backoff
+                    );
+                    retry_at = Some(Instant::now() + backoff);
+                    backoff = (backoff * 2).min(RESCAN_MAX_BACKOFF);
+                }
+            }
+        }
+    }
+
+    /// apply the changes of the NDProxies
+    fn apply(&mut self, control: ProxyControl) {
+        match control {
+            ProxyControl::Add(name, proxy) => {
+                self.proxies.insert(name, proxy);
+            }
+            ProxyControl::Remove(name) => {
+                self.proxies.remove(&name);
+            }
+            ProxyControl::UpdateIfaces(name, proxied_ifaces, forwarded_ifaces) => {
+                if let Some(proxy) = self.proxies.get_mut(&name) {
+                    proxy.proxied_ifaces = proxied_ifaces;
+                    proxy.forwarded_ifaces = forwarded_ifaces;
+                }
+            }
+        }
+        self.update_routing_table();
+    }
+
+    /// rescan the interfaces, and apply the differences,
+    /// the interfaces showing up are matched against the patterns of every NDProxy again
+    async fn reconcile(&mut self, strict: bool) -> Result<(), Error> {
+        let mut ifaces = interfaces::get_all_ifaces();
+        // for picking the interfaces by "type:" and "group:"
+        let mut links = netlink::dump_links(&mut self.links).await?;
+        for (id, iface) in ifaces.iter_mut() {
+            if let Some(info) = links.remove(id) {
+                iface.set_link_info(info);
+            }
+        }
+        for (id, iface) in ifaces.iter() {
+            match self.ifaces.get(id) {
+                None => info!(
+                    "IfaceMonitor: {} (scope id {}) is ready, link-local address {}, hwaddr {}.",
+                    iface.get_name(),
+                    id,
+                    iface.get_link_addr(),
+                    iface.get_hwaddr()
+                ),
+                Some(old) if !old.same_as(iface) => info!(
+                    "IfaceMonitor: {} (scope id {}) has changed, link-local address {}, hwaddr {}.",
+                    iface.get_name(),
+                    id,
+                    iface.get_link_addr(),
+                    iface.get_hwaddr()
+                ),
+                _ => {}
+            }
+        }
+        for (id, iface) in self.ifaces.iter() {
+            if !ifaces.contains_key(id) {
+                info!(
+                    "IfaceMonitor: {} (scope id {}) has gone.",
+                    iface.get_name(),
+                    id
+                );
+            }
+        }
+        self.ifaces = ifaces;
+
+        // follow the addresses of interfaces
+        let mut prefix_changed = false;
+        for proxy in self.proxies.values_mut() {
+            let Some((name, length)) = &proxy.prefix_source else {
+                continue;
+            };
+            let prefix = self
+                .ifaces
+                .values()
+                .find(|iface| iface.get_name() == name)
+                .and_then(|iface| iface.global_prefix(*length));
+            if prefix == proxy.proxied_prefix {
+                continue;
+            }
+            match prefix {
+                Some(prefix) => {
+                    info!(
+                        "IfaceMonitor: The proxied prefix taken from {} is {} now.",
+                        name, prefix
+                    );
+                    proxy.prefix_sender.send_replace(prefix);
+                }
+                None => warn!(
+                    "IfaceMonitor: {} has no global address, stop proxying {}.",
+                    name,
+                    proxy.proxied_prefix.unwrap_or_default()
+                ),
+            }
+            proxy.proxied_prefix = prefix;
+            prefix_changed = true;
+        }
+        if prefix_changed {
+            self.update_routing_table();
+        }
+
+        // what the NDProxies need
+        let mut upstream = HashMap::new();
+        let mut downstream = HashMap::new();
+        let mut learning = HashMap::new();
+        let mut learning_prefixes: LearningPrefixes = HashMap::new();
+        let mut observed = HashMap::new();
+        for proxy in self.proxies.values() {
+            let new_ifaces = (
+                proxy.proxied_ifaces.filter(&self.ifaces),
+                proxy.forwarded_ifaces.filter(&self.ifaces),
+            );
+            upstream.extend(new_ifaces.0.clone());
+            downstream.extend(new_ifaces.1.clone());
+            if proxy.learning {
+                learning.extend(new_ifaces.1.clone());
+                if let Some(prefix) = proxy.local_prefix.or(proxy.proxied_prefix) {
+                    for id in new_ifaces.1.keys() {
+                        learning_prefixes.entry(*id).or_default().push(prefix);
+                    }
+                }
+            }
+            if proxy.observe_nas {
+                observed.extend(new_ifaces.0.clone());
+            }
+            proxy.ifaces_sender.send_if_modified(|old| {
+                let modified =
+                    !same_ifaces(&old.0, &new_ifaces.0) || !same_ifaces(&old.1, &new_ifaces.1);
+                if modified {
+                    *old = new_ifaces;
+                }
+                modified
+            });
+        }
+        self.downstream_sender.send_if_modified(|old| {
+            let modified = !same_ifaces(old, &downstream);
+            if modified {
+                *old = downstream.clone();
+            }
+            modified
+        });
+        self.learning_prefixes.send_if_modified(|old| {
+            let modified = *old != learning_prefixes;
+            if modified {
+                *old = learning_prefixes;
+            }
+            modified
+        });
+
+        // start or stop the monitors
+        let (routing_table, recv_buffer_size) = (&self.routing_table, self.recv_buffer_size);
+        reconcile_monitors(&mut self.ns_monitors, upstream, strict, |iface| {
+            let monitor = NSMonitor::new(routing_table.subscribe(), iface, recv_buffer_size)?;
+            Ok(tokio::spawn(async move {
+                if let Err(e) = monitor.run().await {
+                    error!("NSMonitor exited: {:?}", e);
+                }
+            }))
+        })?;
+        let (neighbors_cache, pending_solicitations, observed_nas) = (
+            &self.neighbors_cache,
+            &self.pending_solicitations,
+            &self.observed_nas,
+        );
+        let spawn_na_monitor = |iface, downstream| {
+            let monitor = NAMonitor::new(
+                iface,
+                neighbors_cache.clone(),
+                pending_solicitations.clone(),
+                observed_nas.clone(),
+                downstream,
+                recv_buffer_size,
+            )?;
+            Ok(tokio::spawn(async move {
+                if let Err(e) = monitor.run().await {
+                    error!("NAMonitor exited: {:?}", e);
+                }
+            }))
+        };
+        observed.retain(|id, _| !downstream.contains_key(id));
+        reconcile_monitors(&mut self.na_monitors, downstream, strict, |iface| {
+            spawn_na_monitor(iface, true)
+        })?;
+        reconcile_monitors(&mut self.observer_monitors, observed, strict, |iface| {
+            spawn_na_monitor(iface, false)
+        })?;
+        let (learning_rate, learning_prefixes) = (self.learning_rate, &self.learning_prefixes);
+        reconcile_monitors(&mut self.learning_monitors, learning, strict, |iface| {
+            let monitor = LearningMonitor::new(
+                iface,
+                learning_prefixes.subscribe(),
+                neighbors_cache.clone(),
+                pending_solicitations.clone(),
+                recv_buffer_size,
+                learning_rate,
+            )?;
+            Ok(tokio::spawn(async move {
+                if let Err(e) = monitor.run().await {
+                    error!("LearningMonitor exited: {:?}", e);
+                }
+            }))
+        })
+    }
+
+    /// swap the routing table of NSMonitors,
+    /// the prefixes taken from interfaces may collide with the others, then the first rule wins
+    fn update_routing_table(&self) {
+        let mut routes: HashMap<Ipv6Net, (&String, SharedNSPacketSender)> = HashMap::new();
+        for (name, proxy) in self.proxies.iter() {
+            let Some(prefix) = proxy.proxied_prefix.map(|prefix| prefix.trunc()) else {
+                continue;
+            };
+            match routes.get(&prefix) {
+                Some((winner, _)) => warn!(
+                    "IfaceMonitor: {} of rule {} is proxied by rule {} already, ignore it.",
+                    prefix, name, winner
+                ),
+                None => {
+                    routes.insert(prefix, (name, proxy.ns_sender.clone()));
+                }
+            }
+        }
+        self.routing_table.send_replace(construst_routing_table(
+            routes
+                .into_iter()
+                .map(|(prefix, (_, sender))| (prefix, sender)),
+        ));
+    }
+}
+
+/// wait for the retry of a failed rescan, forever if there is none
+async fn retry_due(retry_at: Option<Instant>) {
+    match retry_at {
+        Some(retry_at) => tokio::time::sleep_until(retry_at.into()).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// compare two sets of interfaces
+fn same_ifaces(a: &HashMap<u32, NDInterface>, b: &HashMap<u32, NDInterface>) -> bool {
+    a.len() == b.len()
+        && a.iter()
+            .all(|(id, iface)| b.get(id).is_some_and(|other| other.same_as(iface)))
+}
+
+/// make the running monitors match the wanted interfaces
+fn reconcile_monitors<F>(
+    monitors: &mut HashMap<u32, MonitorTask>,
+    wanted: HashMap<u32, NDInterface>,
+    strict: bool,
+    spawn: F,
+) -> Result<(), Error>
+where
+    F: Fn(NDInterface) -> Result<JoinHandle<()>, Error>,
+{
+    // stop the monitors of vanished or changed interfaces, and the ones that have exited
+    monitors.retain(|id, task| {
+        let keep = !task.handle.is_finished()
+            && wanted
+                .get(id)
+                .is_some_and(|iface| iface.same_as(&task.iface));
+        if !keep {
+            task.handle.abort();
+        }
+        keep
+    });
+    for (id, iface) in wanted {
+        if monitors.contains_key(&id) {
+            continue;
+        }
+        match spawn(iface.clone()) {
+            Ok(handle) => {
+                monitors.insert(id, MonitorTask { iface, handle });
+            }
+            Err(e) if strict => return Err(e),
+            Err(e) => error!(
+                "IfaceMonitor: _{:?}_ Failed to start monitor for {}.",
+                e,
+                iface.get_name()
+            ),
+        }
+    }
+    Ok(())
+}
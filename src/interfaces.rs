@@ -1,9 +1,19 @@
 use crate::conf;
+use crate::netlink::LinkInfo;
+use ipnet::Ipv6Net;
 use pnet::datalink;
 use pnet::util::MacAddr;
+use regex::Regex;
 use std::collections::HashMap;
 use std::net::{IpAddr, Ipv6Addr};
 
+/// where the names of interface groups are defined, the first one wins
+const GROUP_FILES: [&str; 3] = [
+    "/etc/iproute2/group",
+    "/usr/share/iproute2/group",
+    "/usr/lib/iproute2/group",
+];
+
 #[derive(getset::Getters, Clone, Debug, PartialEq)]
 pub struct NDInterface {
     #[get = "pub with_prefix"]
@@ -16,6 +26,12 @@ pub struct NDInterface {
     hwaddr: MacAddr,
     #[get = "pub with_prefix"]
     from_pnet: datalink::NetworkInterface,
+    /// bridge, veth, wireguard..., None for the physical ones or if unknown
+    #[get = "pub with_prefix"]
+    kind: Option<String>,
+    /// the interface group, 0 is "default"
+    #[get = "pub with_prefix"]
+    group: u32,
 }
 
 /// convert datalink::NetworkInterface to NDInterface
@@ -44,6 +60,8 @@ fn get_specified_iface(raw: datalink::NetworkInterface) -> Option<NDInterface> {
                     link_addr,
                     hwaddr,
                     from_pnet: raw,
+                    kind: None,
+                    group: 0,
                 });
             }
         }
@@ -51,40 +69,277 @@ fn get_specified_iface(raw: datalink::NetworkInterface) -> Option<NDInterface> {
     None
 }
 
+/// return all of the interfaces that have link-local addresses
+pub fn get_all_ifaces() -> HashMap<u32, NDInterface> {
+    datalink::interfaces()
+        .into_iter()
+        .filter_map(get_specified_iface)
+        .map(|iface| (iface.scope_id, iface))
+        .collect()
+}
+
 /// given a list of names of interfaces, return a list of NDInterfaces
+#[cfg(any(feature = "dev", test))]
 pub fn get_ifaces_with_name(names: &[String]) -> HashMap<u32, NDInterface> {
-    let mut ret = HashMap::new();
+    match IfaceSelector::new(names) {
+        Ok(selector) => selector.filter(&get_all_ifaces()),
+        Err(e) => {
+            log::warn!("{}", e);
+            HashMap::new()
+        }
+    }
+}
+
+/// one of the patterns in proxied_ifaces or forwarded_ifaces
+#[derive(Clone, Debug)]
+enum IfacePattern {
+    /// "*" or "auto"
+    Any,
+    Name(String),
+    /// "wg*"
+    Glob(glob::Pattern),
+    /// "re:^veth[0-9]+$"
+    Regex(Regex),
+    /// "type:bridge", the kind shown by ip -d link
+    Kind(String),
+    /// "group:lan", by name or number
+    Group(u32),
+}
 
-    if names.contains(&String::from("*")) {
-        for iface in datalink::interfaces() {
-            if let Some(v) = get_specified_iface(iface) {
-                ret.insert(v.scope_id, v);
+impl IfacePattern {
+    fn new(pattern: &str) -> Result<Self, String> {
+        if pattern == "*" || pattern == conf::FORWARD_AUTO_STRING {
+            Ok(Self::Any)
+        } else if let Some(re) = pattern.strip_prefix("re:") {
+            Regex::new(re)
+                .map(Self::Regex)
+                .map_err(|e| format!("invalid regex {}: {}", pattern, e))
+        } else if let Some(kind) = pattern.strip_prefix("type:") {
+            match kind.is_empty() {
+                true => Err(format!("no kind of interfaces in {}", pattern)),
+                false => Ok(Self::Kind(kind.to_string())),
             }
+        } else if let Some(group) = pattern.strip_prefix("group:") {
+            lookup_group(group)
+                .map(Self::Group)
+                .ok_or_else(|| format!("unknown interface group in {}", pattern))
+        } else if pattern.is_empty() {
+            Err(String::from("empty interface name"))
+        } else if pattern.contains(['*', '?', '[']) {
+            glob::Pattern::new(pattern)
+                .map(Self::Glob)
+                .map_err(|e| format!("invalid glob {}: {}", pattern, e))
+        } else {
+            Ok(Self::Name(pattern.to_string()))
+        }
+    }
+
+    fn matches(&self, iface: &NDInterface) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Name(name) => *name == iface.name,
+            Self::Glob(glob) => glob.matches(&iface.name),
+            Self::Regex(re) => re.is_match(&iface.name),
+            Self::Kind(kind) => iface.kind.as_ref() == Some(kind),
+            Self::Group(group) => *group == iface.group,
         }
-    } else {
-        for iface in datalink::interfaces() {
-            if names.contains(&iface.name) {
-                if let Some(v) = get_specified_iface(iface) {
-                    ret.insert(v.scope_id, v);
-                }
+    }
+}
+
+/// the number of an interface group, as in ip link set group
+fn lookup_group(name: &str) -> Option<u32> {
+    if let Ok(id) = name.parse() {
+        return Some(id);
+    }
+    GROUP_FILES
+        .iter()
+        .filter_map(|file| std::fs::read_to_string(file).ok())
+        .find_map(|content| {
+            content.lines().find_map(|line| {
+                let mut words = line.split('#').next()?.split_whitespace();
+                let id = words.next()?;
+                (words.next()? == name).then(|| match id.strip_prefix("0x") {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                    None => id.parse().ok(),
+                })?
+            })
+        })
+        // known by iproute2 even without the files
+        .or((name == "default").then_some(0))
+}
+
+/// picks the interfaces matching any of the patterns and none of the "!" ones,
+/// if there are "!" patterns only, all of the other interfaces are picked
+#[derive(Clone, getset::Getters)]
+pub struct IfaceSelector {
+    /// as written in the config
+    #[get = "pub with_prefix"]
+    patterns: Vec<String>,
+    include: Vec<IfacePattern>,
+    exclude: Vec<IfacePattern>,
+}
+
+impl IfaceSelector {
+    pub fn new(patterns: &[String]) -> Result<Self, String> {
+        let mut include = Vec::new();
+        let mut exclude = Vec::new();
+        for pattern in patterns {
+            match pattern.strip_prefix('!') {
+                Some(pattern) => exclude.push(IfacePattern::new(pattern)?),
+                None => include.push(IfacePattern::new(pattern)?),
             }
         }
+        if include.is_empty() && !exclude.is_empty() {
+            include.push(IfacePattern::Any);
+        }
+        Ok(Self {
+            patterns: patterns.to_vec(),
+            include,
+            exclude,
+        })
     }
 
-    ret
+    /// "auto" picks the interface by route, any of the interfaces could be picked
+    pub fn is_auto(&self) -> bool {
+        self.patterns
+            .iter()
+            .any(|pattern| pattern == conf::FORWARD_AUTO_STRING)
+    }
+
+    pub fn matches(&self, iface: &NDInterface) -> bool {
+        self.include.iter().any(|pattern| pattern.matches(iface))
+            && !self.exclude.iter().any(|pattern| pattern.matches(iface))
+    }
+
+    /// pick the NDInterfaces from the candidates
+    pub fn filter(&self, candidates: &HashMap<u32, NDInterface>) -> HashMap<u32, NDInterface> {
+        candidates
+            .iter()
+            .filter(|(_, iface)| self.matches(iface))
+            .map(|(id, iface)| (*id, iface.clone()))
+            .collect()
+    }
+}
+
+/// compiled patterns are equal if they are written the same
+impl PartialEq for IfaceSelector {
+    fn eq(&self, other: &Self) -> bool {
+        self.patterns == other.patterns
+    }
+}
+
+impl Eq for IfaceSelector {}
+
+impl std::fmt::Debug for IfaceSelector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(&self.patterns).finish()
+    }
+}
+
+impl NDInterface {
+    /// what rtnetlink tells beyond datalink::interfaces()
+    pub fn set_link_info(&mut self, info: LinkInfo) {
+        self.kind = info.kind;
+        self.group = info.group;
+    }
+
+    /// whether two NDInterfaces are the same from the view of Neighbor Discovery
+    pub fn same_as(&self, other: &NDInterface) -> bool {
+        self.scope_id == other.scope_id
+            && self.name == other.name
+            && self.link_addr == other.link_addr
+            && self.hwaddr == other.hwaddr
+    }
+
+    /// the prefix of length bits taken from the global unicast address of the interface,
+    /// the lowest address wins if there are many
+    pub fn global_prefix(&self, length: u8) -> Option<Ipv6Net> {
+        self.from_pnet
+            .ips
+            .iter()
+            .filter_map(|addr| match addr.ip() {
+                // 2000::/3
+                IpAddr::V6(ip) if ip.segments()[0] & 0xe000 == 0x2000 => Some(ip),
+                _ => None,
+            })
+            .min()
+            .and_then(|ip| Ipv6Net::new(ip, length).ok())
+            .map(|net| net.trunc())
+    }
 }
 
 /// return the proxied interface and the forwarded interface
 pub fn get_ifaces_defined_by_config(
     ndconf: &conf::NDConfig,
 ) -> (HashMap<u32, NDInterface>, HashMap<u32, NDInterface>) {
-    let proxied_ifaces = get_ifaces_with_name(ndconf.get_proxied_ifaces());
-    let forwarded_ifaces = get_ifaces_with_name(ndconf.get_forwarded_ifaces());
-    (proxied_ifaces, forwarded_ifaces)
+    let ifaces = get_all_ifaces();
+    (
+        ndconf.get_proxied_ifaces().filter(&ifaces),
+        ndconf.get_forwarded_ifaces().filter(&ifaces),
+    )
 }
 
 #[test]
 fn test_get_ifaces_with_name() {
-    let ret = get_ifaces_with_name(&vec![String::from("lo")]);
+    let ret = get_ifaces_with_name(&[String::from("lo")]);
     assert_eq!(ret.len(), 0);
 }
+
+#[test]
+fn test_iface_selector() {
+    let iface = |name: &str, scope_id: u32, kind: Option<&str>, group: u32| {
+        let raw = datalink::NetworkInterface {
+            name: String::from(name),
+            description: String::new(),
+            index: scope_id,
+            mac: None,
+            ips: vec![],
+            flags: 0,
+        };
+        NDInterface {
+            name: String::from(name),
+            scope_id,
+            link_addr: Ipv6Addr::UNSPECIFIED,
+            hwaddr: MacAddr::zero(),
+            from_pnet: raw,
+            kind: kind.map(String::from),
+            group,
+        }
+    };
+    let candidates: HashMap<u32, NDInterface> = [
+        iface("eth0", 1, None, 0),
+        iface("eth1", 2, None, 1),
+        iface("wg0", 3, Some("wireguard"), 0),
+        iface("veth12", 4, Some("veth"), 1),
+        iface("vethx", 5, Some("veth"), 0),
+        iface("br0", 6, Some("bridge"), 1),
+    ]
+    .into_iter()
+    .map(|iface| (iface.scope_id, iface))
+    .collect();
+    let pick = |patterns: &[&str]| {
+        let patterns: Vec<String> = patterns.iter().map(|p| p.to_string()).collect();
+        let mut ret: Vec<u32> = IfaceSelector::new(&patterns)
+            .unwrap()
+            .filter(&candidates)
+            .into_keys()
+            .collect();
+        ret.sort();
+        ret
+    };
+    assert_eq!(pick(&["*"]), vec![1, 2, 3, 4, 5, 6]);
+    assert_eq!(pick(&["auto"]), vec![1, 2, 3, 4, 5, 6]);
+    assert_eq!(pick(&[]), Vec::<u32>::new());
+    assert_eq!(pick(&["eth1", "wg0"]), vec![2, 3]);
+    assert_eq!(pick(&["eth*"]), vec![1, 2]);
+    assert_eq!(pick(&["re:^veth[0-9]+$"]), vec![4]);
+    assert_eq!(pick(&["!eth0"]), vec![2, 3, 4, 5, 6]);
+    assert_eq!(pick(&["eth*", "!eth0"]), vec![2]);
+    assert_eq!(pick(&["type:veth"]), vec![4, 5]);
+    assert_eq!(pick(&["group:1"]), vec![2, 4, 6]);
+    assert_eq!(pick(&["group:1", "!type:bridge"]), vec![2, 4]);
+    assert_eq!(pick(&["group:default", "!type:veth"]), vec![1, 3]);
+    for bad in ["re:(", "type:", "group:nonexistent", "", "!", "[eth"] {
+        assert!(IfaceSelector::new(&[bad.to_string()]).is_err(), "{}", bad);
+    }
+}
@@ -0,0 +1,198 @@
+use crate::datalink::{PacketReceiver, PacketReceiverOpts};
+use crate::error::Error;
+use crate::interfaces::NDInterface;
+use crate::nd_proxy::answer_pending_solicitations;
+use crate::types::*;
+use log::{debug, trace, warn};
+use std::net::Ipv6Addr;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+/// monitors for the traffic of neighbors
+/// the global source addresses seen on a downstream interface are recorded into the neighbor cache,
+/// so that the hosts talking already are known before anyone solicits them
+/// only the local prefixes of the rules are learned, the routed traffic carries the MAC of a router
+pub struct LearningMonitor {
+    inner: PacketReceiver,
+    iface: NDInterface,
+    prefixes: watch::Receiver<LearningPrefixes>,
+    neighbors_cache: NeighborsCache,
+    /// NSes that are waiting for the neighbors of this interface
+    pending_solicitations: PendingSolicitations,
+    limiter: RateLimiter,
+}
+
+impl LearningMonitor {
+    pub fn new(
+        iface: NDInterface,
+        prefixes: watch::Receiver<LearningPrefixes>,
+        neighbors_cache: NeighborsCache,
+        pending_solicitations: PendingSolicitations,
+        recv_buffer_size: usize,
+        learning_rate: u32,
+    ) -> Result<Self, Error> {
+        let inner = PacketReceiver::new(recv_buffer_size)?;
+        inner.bind_to_interface(&iface)?;
+        inner.set_filter_pass_ipv6_global_src()?;
+
+        Ok(Self {
+            inner,
+            iface,
+            prefixes,
+            neighbors_cache,
+            pending_solicitations,
+            limiter: RateLimiter::new(learning_rate),
+        })
+    }
+
+    /// main loop: record the source of every packet as a neighbor
+    pub async fn run(mut self) -> Result<(), Error> {
+        warn!(
+            "LearningMonitor for {}: Start to work",
+            self.iface.get_name()
+        );
+        loop {
+            let (packet, hwaddr) = self.inner.recv_pkt_from().await?;
+            // the filter passes the ipv6 header up to the source address
+            if packet.len() < 24 || !hwaddr.is_unicast() || hwaddr.is_zero() {
+                continue;
+            }
+            let src_addr =
+                unsafe { address_translation::construct_v6addr_unchecked(&packet[8..24]) };
+            let key = (*self.iface.get_scope_id(), src_addr);
+            if !learnable(&self.prefixes.borrow(), key) {
+                trace!(
+                    "LearningMonitor for {}: {} is out of the local prefixes, ignore it.",
+                    self.iface.get_name(),
+                    src_addr
+                );
+                continue;
+            }
+            // nothing to learn from a known neighbor
+            if self
+                .neighbors_cache
+                .get(&key)
+                .is_some_and(|entry| entry.is_usable() && *entry.get_hwaddr() == Some(hwaddr))
+            {
+                continue;
+            }
+            let now = Instant::now();
+            if !self.limiter.allow(now) {
+                continue;
+            }
+            if let Some(dropped) = self.limiter.take_dropped() {
+                debug!(
+                    "LearningMonitor for {}: {} neighbors were not learned for the rate limit.",
+                    self.iface.get_name(),
+                    dropped
+                );
+            }
+            debug!(
+                "LearningMonitor for {}: Learn {} at {}.",
+                self.iface.get_name(),
+                src_addr,
+                hwaddr
+            );
+            // the traffic does not prove the reachability, so the neighbor is STALE and probed on use
+            self.neighbors_cache.confirm(key, Some(hwaddr), false, true);
+            // wake up the NDProxies that are waiting for this neighbor
+            answer_pending_solicitations(&self.pending_solicitations, key).await;
+        }
+    }
+}
+
+/// whether the source address is in a local prefix of the interface
+fn learnable(prefixes: &LearningPrefixes, (scope_id, src_addr): (u32, Ipv6Addr)) -> bool {
+    prefixes
+        .get(&scope_id)
+        .is_some_and(|prefixes| prefixes.iter().any(|prefix| prefix.contains(&src_addr)))
+}
+
+/// at most rate neighbors are learned in a second
+struct RateLimiter {
+    rate: u32,
+    window_start: Option<Instant>,
+    allowed: u32,
+    /// the neighbors not learned, reported once the next window starts
+    dropped: u64,
+    reported: Option<u64>,
+}
+
+impl RateLimiter {
+    fn new(rate: u32) -> Self {
+        Self {
+            rate,
+            window_start: None,
+            allowed: 0,
+            dropped: 0,
+            reported: None,
+        }
+    }
+
+    fn allow(&mut self, now: Instant) -> bool {
+        if self
+            .window_start
+            .is_none_or(|start| now >= start + Duration::from_secs(1))
+        {
+            self.window_start = Some(now);
+            self.allowed = 0;
+            if self.dropped > 0 {
+                self.reported = Some(std::mem::take(&mut self.dropped));
+            }
+        }
+        if self.allowed < self.rate {
+            self.allowed += 1;
+            true
+        } else {
+            self.dropped += 1;
+            false
+        }
+    }
+
+    /// the neighbors dropped in the last window, once
+    fn take_dropped(&mut self) -> Option<u64> {
+        self.reported.take()
+    }
+}
+
+#[test]
+fn test_rate_limiter() {
+    let now = Instant::now();
+    let mut limiter = RateLimiter::new(2);
+    assert!(limiter.allow(now));
+    assert!(limiter.allow(now + Duration::from_millis(10)));
+    assert!(!limiter.allow(now + Duration::from_millis(20)));
+    assert!(!limiter.allow(now + Duration::from_millis(999)));
+    assert_eq!(limiter.take_dropped(), None);
+    assert!(limiter.allow(now + Duration::from_secs(1)));
+    assert_eq!(limiter.take_dropped(), Some(2));
+    assert_eq!(limiter.take_dropped(), None);
+    assert!(limiter.allow(now + Duration::from_millis(1500)));
+    assert!(!limiter.allow(now + Duration::from_millis(1600)));
+}
+
+#[test]
+fn test_learnable() {
+    let prefix = |s: &str| s.parse::<ipnet::Ipv6Net>().unwrap();
+    let prefixes: LearningPrefixes = std::collections::HashMap::from([
+        (2, vec![prefix("2001:db8:1::/64"), prefix("2001:db9::/64")]),
+        (3, vec![prefix("2001:db8:3::/64")]),
+    ]);
+    for (scope_id, addr, learned) in [
+        (2, "2001:db8:1::5", true),
+        (2, "2001:db9::5", true),
+        // routed from elsewhere, with the MAC of the router
+        (2, "2001:db8:2::5", false),
+        (2, "2001:db8:3::5", false),
+        (3, "2001:db8:3::5", true),
+        (4, "2001:db8:1::5", false),
+    ] {
+        assert_eq!(
+            learnable(&prefixes, (scope_id, addr.parse().unwrap())),
+            learned,
+            "{}%{}",
+            addr,
+            scope_id
+        );
+    }
+}
@@ -0,0 +1,333 @@
+use crate::types::LeaseFormat;
+use std::collections::HashMap;
+use std::net::Ipv6Addr;
+
+/// address -> when the lease ends in seconds since the epoch, None for infinite leases
+pub type LeaseEnds = HashMap<Ipv6Addr, Option<u64>>;
+
+/// the active IA_NA leases in a lease file of a DHCPv6 server,
+/// and the number of entries that are not understood
+pub fn parse_leases(format: LeaseFormat, content: &str) -> (LeaseEnds, usize) {
+    match format {
+        LeaseFormat::Dnsmasq => parse_dnsmasq(content),
+        LeaseFormat::Isc => parse_isc(content),
+        LeaseFormat::Kea => parse_kea(content),
+    }
+}
+
+/// dnsmasq.leases, the IPv6 leases follow the "duid" line of the server:
+/// ```
+/// <end> <iaid> <address> <hostname> <client duid>
+/// ```
+/// the iaid of IA_TA starts with "T", and 0 ends never
+fn parse_dnsmasq(content: &str) -> (LeaseEnds, usize) {
+    let mut ret = HashMap::new();
+    let mut skipped = 0;
+    for line in content.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.as_slice() {
+            [] | ["duid", ..] => {}
+            [end, iaid, addr, ..] => match (end.parse::<u64>(), addr.parse::<Ipv6Addr>()) {
+                (Ok(_), Ok(_)) if iaid.starts_with('T') => {}
+                (Ok(end), Ok(addr)) => {
+                    ret.insert(addr, (end != 0).then_some(end));
+                }
+                // IPv4 leases
+                (Ok(_), Err(_)) if addr.contains('.') => {}
+                _ => skipped += 1,
+            },
+            _ => skipped += 1,
+        }
+    }
+    (ret, skipped)
+}
+
+/// dhcpd6.leases of ISC dhcpd, the later entries of an address replace the earlier ones:
+/// ```
+/// ia-na "<iaid and duid>" {
+///   iaaddr 2001:db8::100 {
+///     binding state active;
+///     ends 4 2024/01/18 10:10:00;
+///   }
+/// }
+/// ```
+/// ends could be "never", or "epoch <seconds>;" with db-time-format local
+fn parse_isc(content: &str) -> (LeaseEnds, usize) {
+    let mut ret = HashMap::new();
+    let mut skipped = 0;
+    // the blocks that we are in, with the address of iaaddr
+    let mut blocks: Vec<(String, Option<Ipv6Addr>)> = Vec::new();
+    // the state and the end of the current iaaddr
+    let mut lease: (Option<String>, Option<Option<u64>>) = (None, None);
+    let mut statement: Vec<String> = Vec::new();
+    for token in isc_tokens(content) {
+        match token.as_str() {
+            "{" => {
+                let addr = match statement.as_slice() {
+                    [iaaddr, addr] if iaaddr == "iaaddr" => {
+                        let addr = addr.parse().ok();
+                        skipped += addr.is_none() as usize;
+                        addr
+                    }
+                    _ => None,
+                };
+                blocks.push((statement.first().cloned().unwrap_or_default(), addr));
+                lease = (None, None);
+                statement.clear();
+            }
+            "}" => {
+                statement.clear();
+                let Some((kind, addr)) = blocks.pop() else {
+                    skipped += 1;
+                    continue;
+                };
+                let in_ia_na = blocks.last().is_some_and(|(kind, _)| kind == "ia-na");
+                let (Some(addr), true) = (addr, kind == "iaaddr" && in_ia_na) else {
+                    continue;
+                };
+                match std::mem::take(&mut lease) {
+                    (Some(state), Some(end)) if state == "active" => {
+                        ret.insert(addr, end);
+                    }
+                    (Some(state), _) if state != "active" => {
+                        ret.remove(&addr);
+                    }
+                    _ => skipped += 1,
+                }
+            }
+            ";" => {
+                match statement.as_slice() {
+                    [binding, state, value] if binding == "binding" && state == "state" => {
+                        lease.0 = Some(value.clone());
+                    }
+                    [ends, never] if ends == "ends" && never == "never" => lease.1 = Some(None),
+                    [ends, epoch, secs] if ends == "ends" && epoch == "epoch" => {
+                        lease.1 = secs.parse().ok().map(Some);
+                    }
+                    [ends, _weekday, date, time, ..] if ends == "ends" => {
+                        lease.1 = isc_time(date, time).map(Some);
+                    }
+                    _ => {}
+                }
+                statement.clear();
+            }
+            _ => statement.push(token),
+        }
+    }
+    (ret, skipped)
+}
+
+/// split into words, quoted strings, and "{", "}", ";", without the comments
+fn isc_tokens(content: &str) -> Vec<String> {
+    let mut ret = Vec::new();
+    let mut chars = content.chars();
+    let mut word = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                // the DUIDs are quoted, with escapes
+                word.push(c);
+                while let Some(c) = chars.next() {
+                    word.push(c);
+                    match c {
+                        '\\' => word.extend(chars.next()),
+                        '"' => break,
+                        _ => {}
+                    }
+                }
+            }
+            '#' => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '{' | '}' | ';' => {
+                if !word.is_empty() {
+                    ret.push(std::mem::take(&mut word));
+                }
+                ret.push(c.to_string());
+            }
+            c if c.is_whitespace() => {
+                if !word.is_empty() {
+                    ret.push(std::mem::take(&mut word));
+                }
+            }
+            c => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        ret.push(word);
+    }
+    ret
+}
+
+/// "2024/01/18" "10:10:00" in UTC, to seconds since the epoch
+fn isc_time(date: &str, time: &str) -> Option<u64> {
+    let date: Vec<i64> = date
+        .split('/')
+        .map(|v| v.parse().ok())
+        .collect::<Option<_>>()?;
+    let time: Vec<u64> = time
+        .split(':')
+        .map(|v| v.parse().ok())
+        .collect::<Option<_>>()?;
+    let ([y, m, d], [hh, mm, ss]) = (date.as_slice(), time.as_slice()) else {
+        return None;
+    };
+    if !(1..=12).contains(m) || !(1..=31).contains(d) {
+        return None;
+    }
+    // days from 1970-01-01, the proleptic Gregorian calendar
+    let y = if *m <= 2 { y - 1 } else { *y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((m + 9) % 12) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = u64::try_from(era * 146097 + doe - 719468).ok()?;
+    Some(days * 86400 + hh * 3600 + mm * 60 + ss)
+}
+
+/// kea-leases6.csv of the memfile backend, the later rows of an address replace the earlier ones,
+/// the columns are named by the header:
+/// ```
+/// address,duid,valid_lifetime,expire,subnet_id,pref_lifetime,lease_type,iaid,prefix_len,...,state,...
+/// ```
+/// lease_type 0 is IA_NA, state 0 is active, and valid_lifetime 0 means the lease is deleted
+fn parse_kea(content: &str) -> (LeaseEnds, usize) {
+    let mut ret = HashMap::new();
+    let mut skipped = 0;
+    let mut lines = content.lines();
+    let Some(header) = lines.next() else {
+        return (ret, skipped);
+    };
+    let column = |name: &str| header.split(',').position(|v| v.trim() == name);
+    let (Some(address), Some(valid_lifetime), Some(expire), Some(lease_type)) = (
+        column("address"),
+        column("valid_lifetime"),
+        column("expire"),
+        column("lease_type"),
+    ) else {
+        return (ret, content.lines().count());
+    };
+    let state = column("state");
+    for line in lines.filter(|line| !line.trim().is_empty()) {
+        let fields: Vec<&str> = line.split(',').collect();
+        let field = |i: usize| fields.get(i).map(|v| v.trim());
+        let (Some(Ok(addr)), Some(Ok(valid_lifetime)), Some(Ok(expire)), Some(lease_type)) = (
+            field(address).map(|v| v.parse::<Ipv6Addr>()),
+            field(valid_lifetime).map(|v| v.parse::<u64>()),
+            field(expire).map(|v| v.parse::<u64>()),
+            field(lease_type),
+        ) else {
+            skipped += 1;
+            continue;
+        };
+        if lease_type != "0" {
+            continue;
+        }
+        let active = state.and_then(field).is_none_or(|state| state == "0");
+        match valid_lifetime != 0 && active {
+            true => ret.insert(addr, Some(expire)),
+            false => ret.remove(&addr),
+        };
+    }
+    (ret, skipped)
+}
+
+#[test]
+fn test_parse_leases() {
+    let addr = |s: &str| s.parse::<Ipv6Addr>().unwrap();
+
+    let (leases, skipped) = parse_leases(
+        LeaseFormat::Dnsmasq,
+        "1705572600 02:00:00:00:00:01 192.168.1.10 host4 01:02:00:00:00:00:01\n\
+         duid 00:01:00:01:2d:00:00:00:02:00:00:00:00:ff\n\
+         1705572600 1234 2001:db8::100 vm1 00:01:00:01:2d:00:00:00:02:00:00:00:00:01\n\
+         0 1235 2001:db8::101 * 00:01:00:01:2d:00:00:00:02:00:00:00:00:02\n\
+         1705572600 T1236 2001:db8::102 * 00:01:00:01:2d:00:00:00:02:00:00:00:00:03\n\
+         garbage\n",
+    );
+    assert_eq!(
+        leases,
+        HashMap::from([
+            (addr("2001:db8::100"), Some(1705572600)),
+            (addr("2001:db8::101"), None)
+        ])
+    );
+    assert_eq!(skipped, 1);
+
+    let (leases, skipped) = parse_leases(
+        LeaseFormat::Isc,
+        r#"# The format of this file is documented in the dhcpd.leases(5) manual page.
+server-duid "\000\001\000\001-\000\000\000\002\000\000\000\000\377";
+
+ia-na "\001\000\000\000\000\001\000\001{;\000" {
+  cltt 4 2024/01/18 10:00:00;
+  iaaddr 2001:db8::100 {
+    binding state active;
+    preferred-life 375;
+    max-life 600;
+    ends 4 2024/01/18 10:10:00;
+  }
+}
+ia-na "\002\000\000\000\000\001\000\001" {
+  iaaddr 2001:db8::101 {
+    binding state active;
+    ends never;
+  }
+  iaaddr 2001:db8::102 {
+    binding state active;
+    ends epoch 1705572600; # Thu Jan 18 10:10:00 2024
+  }
+}
+ia-ta "\003\000\000\000" {
+  iaaddr 2001:db8::103 {
+    binding state active;
+    ends 4 2024/01/18 10:10:00;
+  }
+}
+ia-pd "\004\000\000\000" {
+  iaprefix 2001:db8:1::/64 {
+    binding state active;
+    ends 4 2024/01/18 10:10:00;
+  }
+}
+ia-na "\002\000\000\000\000\001\000\001" {
+  iaaddr 2001:db8::101 {
+    binding state expired;
+    ends 4 2024/01/18 10:10:00;
+  }
+}
+"#,
+    );
+    assert_eq!(
+        leases,
+        HashMap::from([
+            (addr("2001:db8::100"), Some(1705572600)),
+            (addr("2001:db8::102"), Some(1705572600))
+        ])
+    );
+    assert_eq!(skipped, 0);
+
+    let (leases, skipped) = parse_leases(
+        LeaseFormat::Kea,
+        "address,duid,valid_lifetime,expire,subnet_id,pref_lifetime,lease_type,iaid,prefix_len,fqdn_fwd,fqdn_rev,hostname,hwaddr,state,user_context,hwtype,hwaddr_source,pool_id\n\
+         2001:db8::100,00:01:00:01,600,1705572600,1,375,0,1,128,0,0,vm1,02:00:00:00:00:01,0,,1,2,0\n\
+         2001:db8::101,00:01:00:02,600,1705572600,1,375,0,2,128,0,0,vm2,02:00:00:00:00:02,0,,1,2,0\n\
+         2001:db8::102,00:01:00:03,600,1705572600,1,375,0,3,128,0,0,vm3,02:00:00:00:00:03,1,,1,2,0\n\
+         2001:db8:1::,00:01:00:04,600,1705572600,1,375,2,4,64,0,0,,,0,,1,2,0\n\
+         2001:db8::101,00:01:00:02,0,1705572000,1,0,0,2,128,0,0,vm2,02:00:00:00:00:02,0,,1,2,0\n\
+         zzz,00:01:00:05,600,1705572600,1,375,0,5,128,0,0,,,0,,1,2,0\n",
+    );
+    assert_eq!(
+        leases,
+        HashMap::from([(addr("2001:db8::100"), Some(1705572600))])
+    );
+    assert_eq!(skipped, 1);
+
+    assert_eq!(isc_time("1970/01/01", "00:00:00"), Some(0));
+    assert_eq!(isc_time("2000/03/01", "00:00:01"), Some(951868801));
+    assert_eq!(isc_time("2024/13/01", "00:00:00"), None);
+}
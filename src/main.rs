@@ -1,24 +1,24 @@
+mod autowire; // installing host routes for downstream neighbors
 mod conf; // config file
 mod datalink; // about sending and receiving pkts
 mod error; // error types
+mod file_monitor; // following the changes of files
+mod hosts; // the targets of static rules
+mod iface_monitor; // following the changes of interfaces
 mod interfaces; // find interface by name / config
+mod learning_monitor; // learning neighbors from their traffic
+mod leases; // the lease files of DHCPv6 servers
 mod na_monitor; // monitoring NA pkts
 mod nd_proxy; // main process?
+mod neighbors; // neighbor cache
+mod netlink; // talking to the kernel via rtnetlink
 mod ns_monitor; // monitoring NS pkts
+mod offload; // letting the kernel answer NSes
 mod packets; // about encoding/decoding pkts
 mod routing; // a _route_ table
+mod supervisor; // starting, stopping and reloading NDProxies
 mod types; // self-defined types
 
-use crate::na_monitor::NAMonitor;
-use crate::ns_monitor::NSMonitor;
-use crate::routing::construst_routing_table;
-use conf::TTL_OF_CACHE;
-use futures::FutureExt;
-use futures::future::select_all;
-use r_cache::cache::Cache;
-use std::collections::HashMap;
-use std::sync::Arc;
-
 use clap::Parser;
 
 #[cfg(not(feature = "dev"))]
@@ -33,7 +33,6 @@ struct Args {
 #[cfg(not(feature = "dev"))]
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<(), error::Error> {
-    pretty_env_logger::init();
     let args = Args::parse();
     ndproxy_main(args.config).await
 }
@@ -76,8 +75,11 @@ enum Commands {
 #[cfg(feature = "dev")]
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<(), error::Error> {
-    pretty_env_logger::init();
     let args = Args::parse();
+    // ndproxy_main sets up the logger by itself
+    if args.command.is_some() {
+        pretty_env_logger::init();
+    }
     match &args.command {
         Some(Commands::Namonitor) => dev::namonitor(&[args.monitor_this_interface.unwrap()]).await,
         Some(Commands::Nsmonitor) => dev::nsmonitor(&[args.monitor_this_interface.unwrap()]).await,
@@ -100,59 +102,26 @@ async fn main() -> Result<(), error::Error> {
 }
 
 async fn ndproxy_main(config_filename: String) -> Result<(), error::Error> {
-    // parse the config file
-    let myconf = conf::parse_config(&config_filename)?;
-
-    //
-    let mut monitored_ns_ifaces = HashMap::new();
-    let mut monitored_na_ifaces = HashMap::new();
-    let mut route_map = std::collections::HashMap::new();
-    let neighbors_cache = Arc::new(Cache::new(Some(TTL_OF_CACHE)));
+    // parse the config file, [global] decides how to log the rest
+    let myconf = conf::read_config(&config_filename)?;
+    let global = conf::parse_global(&config_filename, &myconf)?;
+    init_logger(global.get_log_level());
+    let rules = conf::parse_rules(&config_filename, &myconf, &global)?;
 
-    // prepare proxies for proxied_prefixes
-    let mut tasks = Vec::new();
-
-    for conf in myconf.into_iter() {
-        // update the monitors interfaces (by config)
-        let (upstream_ifaces, downstream_ifaces) = interfaces::get_ifaces_defined_by_config(&conf);
-        monitored_ns_ifaces.extend(upstream_ifaces);
-        monitored_na_ifaces.extend(downstream_ifaces);
-        //
-        let mut ndproxy = nd_proxy::NDProxy::new(conf, neighbors_cache.clone())?;
-        // route prefix to its corresponding ndproxy
-        route_map.insert(
-            *ndproxy.get_proxied_prefix(),
-            ndproxy.get_mpsc_sender_mut().take().unwrap_or_else(|| {
-                panic!(
-                    "cannot take mpsc sender from ndproxy of {}",
-                    ndproxy.get_proxied_prefix()
-                )
-            }),
-        );
-        tasks.push(ndproxy.run().boxed());
-    }
+    // main loop, if any task failed, return the Result and exit?
+    supervisor::Supervisor::start(global, rules)?
+        .run(config_filename)
+        .await
+}
 
-    // prepare monitors for Neighbor Solicitations
-    for nsmonitor in monitored_ns_ifaces
-        .into_values()
-        .map(|iface| NSMonitor::new(construst_routing_table(route_map.clone()), iface))
-    {
-        tasks.push(nsmonitor?.run().boxed())
+/// RUST_LOG, with the log level of ndproxy overridden by the config file
+fn init_logger(level: Option<log::LevelFilter>) {
+    let mut builder = pretty_env_logger::formatted_builder();
+    if let Ok(filters) = std::env::var("RUST_LOG") {
+        builder.parse_filters(&filters);
     }
-
-    // prepare monitors for Neighbor Advertisements
-    for namonitor in monitored_na_ifaces
-        .into_values()
-        .map(|iface| NAMonitor::new(iface, neighbors_cache.clone()))
-    {
-        tasks.push(namonitor?.run().boxed())
+    if let Some(level) = level {
+        builder.filter_module("ndproxy", level);
     }
-
-    // because route_map contains mpsc::Sender, I will drop it to make these Senders unavailable
-    drop(route_map);
-    // drop unused Arc
-    drop(neighbors_cache);
-
-    // main loop, if any task failed, return the Result and exit?
-    select_all(tasks).await.0
+    builder.init();
 }
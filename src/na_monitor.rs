@@ -1,8 +1,12 @@
 use crate::datalink::{PacketReceiver, PacketReceiverOpts};
 use crate::error::Error;
 use crate::interfaces::NDInterface;
+use crate::nd_proxy::answer_pending_solicitations;
+use crate::packets;
 use crate::types::*;
 use log::{debug, warn};
+use pnet::packet::icmpv6::ndp::NeighborAdvertFlags;
+use std::time::Instant;
 
 /// monitors for Neighbor Solicitation
 /// the received packet will be sent to the corresponding NDProxy via mpsc
@@ -15,11 +19,25 @@ pub struct NAMonitor {
     iface: NDInterface,
     /// manage ndp myself
     neighbors_cache: NeighborsCache,
+    /// NSes that are waiting for NAs from this interface
+    pending_solicitations: PendingSolicitations,
+    /// the neighbors are updated on downstream interfaces only,
+    /// upstream ones are watched for the NAs of the real owners
+    downstream: bool,
+    /// for cancelling the delayed NAs of NDProxies
+    observed_nas: ObservedNAs,
 }
 
 impl NAMonitor {
-    pub fn new(iface: NDInterface, neighbors_cache: NeighborsCache) -> Result<Self, Error> {
-        let inner = PacketReceiver::new()?;
+    pub fn new(
+        iface: NDInterface,
+        neighbors_cache: NeighborsCache,
+        pending_solicitations: PendingSolicitations,
+        observed_nas: ObservedNAs,
+        downstream: bool,
+        recv_buffer_size: usize,
+    ) -> Result<Self, Error> {
+        let inner = PacketReceiver::new(recv_buffer_size)?;
         inner.bind_to_interface(&iface)?;
         inner.set_allmulti(&iface)?;
         inner.set_filter_pass_ipv6_na()?;
@@ -28,6 +46,9 @@ impl NAMonitor {
             inner,
             iface,
             neighbors_cache,
+            pending_solicitations,
+            downstream,
+            observed_nas,
         })
     }
 
@@ -58,9 +79,33 @@ impl NAMonitor {
                     tgt_addr,
                 );
             }
-            // update ttl cache
-            self.neighbors_cache
-                .set((*self.iface.get_scope_id(), *tgt_addr), (), None);
+            let (flags, tgt_hwaddr) = match packets::parse_NA_packet(&shared_packet[40..]) {
+                Some(v) => v,
+                None => continue,
+            };
+            // someone has advertised the target
+            let key = (*self.iface.get_scope_id(), *tgt_addr);
+            self.observed_nas
+                .set(key, (Instant::now(), tgt_hwaddr), None);
+            if !self.downstream {
+                continue;
+            }
+            // update neighbor cache
+            self.neighbors_cache.confirm(
+                key,
+                tgt_hwaddr,
+                flags & NeighborAdvertFlags::Solicited != 0,
+                flags & NeighborAdvertFlags::Override != 0,
+            );
+            if !self
+                .neighbors_cache
+                .get(&key)
+                .is_some_and(|entry| entry.is_usable())
+            {
+                continue;
+            }
+            // wake up the NDProxies that are waiting for this neighbor
+            answer_pending_solicitations(&self.pending_solicitations, key).await;
         }
     }
 }
@@ -1,15 +1,135 @@
-use crate::conf::{MPSC_CAPACITY, NDConfig};
+use crate::autowire;
+use crate::conf::{ND_HOP_LIMIT, NDConfig};
 use crate::datalink::{PacketSender, PacketSenderOpts};
+use crate::file_monitor::FileMonitor;
+use crate::hosts::{self, StaticHosts};
 use crate::interfaces::{NDInterface, get_ifaces_defined_by_config};
+use crate::leases;
+use crate::neighbors::{NegativeCache, NeighborState, random_up_to};
+use crate::netlink::{NetlinkMessage, NetlinkSocket, ndmsg};
+use crate::offload::ProxyLifetime;
 use crate::types::*;
 use crate::{error::Error, packets};
 use ipnet::Ipv6Net;
-use log::{info, trace, warn};
+use log::{debug, error, info, trace, warn};
 use pnet::packet::Packet;
+use pnet::packet::icmpv6::ndp::{NeighborAdvertFlags, NeighborAdvertPacket};
 use pnet::util::MacAddr;
-use std::collections::HashMap;
+use r_cache::cache::Cache;
+use std::collections::{HashMap, VecDeque};
 use std::net::{Ipv6Addr, SocketAddrV6};
-use tokio::sync::mpsc;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{mpsc, watch};
+
+/// where the NAs for Duplicate Address Detection go
+const ALL_NODES: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1);
+
+/// a NA waiting for its random delay, RFC 4861 section 7.2.7
+struct DelayedNA {
+    due: Instant,
+    /// the NA is cancelled if someone else advertises the target after this
+    queued: Instant,
+    scope_id: u32,
+    ns_origin: Ipv6Addr,
+    tgt_addr: Ipv6Addr,
+    hwaddr: Option<MacAddr>,
+}
+
+/// the targets advertised on upstream interfaces by someone else with another MAC
+struct Conflicts {
+    /// how long such a target is not answered, zero disables it
+    hold_time: Duration,
+    /// the conflicts reported, (scope id, target address) -> when the foreign NA was seen
+    reported: HashMap<(u32, Ipv6Addr), Instant>,
+}
+
+impl Conflicts {
+    fn new(hold_time: Duration) -> Self {
+        Conflicts {
+            hold_time,
+            reported: HashMap::new(),
+        }
+    }
+
+    /// whether the target I advertise at tgt_hwaddr is in conflict with the NA observed for it,
+    /// along with the foreign MAC the first time, so that a conflict is reported once until it is over
+    fn check(
+        &mut self,
+        key: (u32, Ipv6Addr),
+        observed: Option<(Instant, Option<MacAddr>)>,
+        tgt_hwaddr: MacAddr,
+        now: Instant,
+    ) -> (bool, Option<MacAddr>) {
+        let Some((seen, Some(foreign_hwaddr))) = observed else {
+            return (false, None);
+        };
+        if self.hold_time.is_zero() || foreign_hwaddr == tgt_hwaddr || seen + self.hold_time <= now
+        {
+            return (false, None);
+        }
+        let hold_time = self.hold_time;
+        self.reported.retain(|_, seen| *seen + hold_time > now);
+        match self.reported.insert(key, seen) {
+            None => (true, Some(foreign_hwaddr)),
+            Some(_) => (true, None),
+        }
+    }
+}
+
+/// the NAs waiting for their delay, the oldest ones are dropped beyond depth
+struct DelayedNAs {
+    queue: VecDeque<DelayedNA>,
+    depth: usize,
+}
+
+impl DelayedNAs {
+    fn new(depth: usize) -> Self {
+        DelayedNAs {
+            queue: VecDeque::new(),
+            depth,
+        }
+    }
+
+    /// queue the NA, unless the same NS is being answered already (a retransmission),
+    /// return the NA dropped to make room
+    fn push(&mut self, na: DelayedNA) -> Option<DelayedNA> {
+        if self.queue.iter().any(|q| {
+            (q.scope_id, q.ns_origin, q.tgt_addr) == (na.scope_id, na.ns_origin, na.tgt_addr)
+        }) {
+            return None;
+        }
+        let dropped = if self.queue.len() >= self.depth {
+            self.queue.pop_front()
+        } else {
+            None
+        };
+        self.queue.push_back(na);
+        dropped
+    }
+
+    /// take the NAs due at now, and split them into the ones to send
+    /// and the ones whose targets have been advertised by someone else since they were queued
+    fn take_due(
+        &mut self,
+        now: Instant,
+        observed_nas: &ObservedNAs,
+    ) -> (Vec<DelayedNA>, Vec<DelayedNA>) {
+        let (due, waiting): (VecDeque<_>, VecDeque<_>) = std::mem::take(&mut self.queue)
+            .into_iter()
+            .partition(|na| na.due <= now);
+        self.queue = waiting;
+        due.into_iter().partition(|na| {
+            observed_nas
+                .get(&(na.scope_id, na.tgt_addr))
+                .is_none_or(|(seen, _)| seen < na.queued)
+        })
+    }
+
+    fn next_due(&self) -> Option<Instant> {
+        self.queue.iter().map(|na| na.due).min()
+    }
+}
 
 /// proxy for Neighbor Discovery requests
 /// it will: 0. receive Neighbor Solicitation provided by NSMonitor
@@ -31,27 +151,111 @@ pub struct NDProxy {
     #[get_mut = "pub with_prefix"]
     mpsc_sender: Option<SharedNSPacketSender>,
     pkt_sender: PacketSender,
+    /// the R and O flags of the proxied NAs, S is set by the NS
     na_flag: u8,
+    /// for the NSes of Duplicate Address Detection
+    dad_policy: DadPolicy,
+    /// the link-layer address advertised for the targets
+    advertised_hwaddr: AdvertisedHwaddr,
+    /// the NAs are sent after na_delay plus a random time up to na_jitter
+    na_delay: Duration,
+    na_jitter: Duration,
+    delayed_nas: DelayedNAs,
+    /// the NAs seen on upstream interfaces, for cancelling the delayed ones and detecting conflicts
+    observed_nas: ObservedNAs,
+    /// the targets advertised by someone else with another MAC, which are not answered for a while
+    conflicts: Conflicts,
     /// manage ndp myself
     neighbors_cache: NeighborsCache,
+    /// targets that failed address resolution recently
+    negative_cache: NegativeCache,
+    /// the targets a static rule answers for, None for all of the proxied prefix
+    static_hosts: Option<StaticHosts>,
+    /// the hosts in config, the ones in hosts_file are added to them
+    config_hosts: Vec<Ipv6Net>,
+    hosts_file: Option<FileMonitor>,
+    /// the lease file of a DHCPv6 server
+    leases_file: Option<(FileMonitor, LeaseFormat)>,
+    /// proxied address -> when its lease ends, None for infinite leases
+    leases: HashMap<Ipv6Addr, Option<Instant>>,
+    /// NSes waiting for NAs from downstream interfaces
+    pending_solicitations: PendingSolicitations,
+    /// for NAMonitor to wake me up when a pending NS is answered
+    pending_sender: PendingNSSender,
+    pending_receiver: PendingNSReceiver,
     upstream_ifs: HashMap<u32, NDInterface>,
     downstream_ifs: HashMap<u32, NDInterface>,
+    /// for IfaceMonitor to update upstream_ifs and downstream_ifs
+    #[get_mut = "pub with_prefix"]
+    ifaces_sender: Option<ProxyIfacesSender>,
+    ifaces_receiver: ProxyIfacesReceiver,
+    /// for IfaceMonitor to update proxied_prefix, if it follows the address of an interface
+    #[get_mut = "pub with_prefix"]
+    prefix_sender: Option<ProxiedPrefixSender>,
+    prefix_receiver: ProxiedPrefixReceiver,
+    /// for "auto" forwarded_ifaces, NSes are only sent to the interface the target is routed to
+    kernel_routes: Option<SharedKernelRoutes>,
+    /// for the "kernel" neighbor oracle, targets are resolved by the kernel instead of me
+    kernel_neighbors: Option<SharedKernelNeighbors>,
+    /// for asking the kernel to resolve targets
+    neigh_socket: Option<NetlinkSocket>,
+    /// for asking Autowire to route the confirmed targets
+    autowire: Option<AutowireSender>,
+    /// the routes made by Autowire, the ones of my old prefix are removed when it changes
+    host_routes: SharedHostRoutes,
+    /// for asking ProxyOffload to let the kernel answer NSes
+    offload: Option<OffloadSender>,
+    /// the proxy entries in the kernel, NSes for them are answered by the kernel
+    proxy_entries: SharedProxyEntries,
 }
 
 impl NDProxy {
-    pub fn new(config: NDConfig, neighbors_cache: NeighborsCache) -> Result<Self, Error> {
+    pub fn new(
+        config: NDConfig,
+        neighbors_cache: NeighborsCache,
+        (pending_solicitations, observed_nas): (PendingSolicitations, ObservedNAs),
+        kernel_routes: SharedKernelRoutes,
+        kernel_neighbors: SharedKernelNeighbors,
+        (autowire, host_routes): (AutowireSender, SharedHostRoutes),
+        (offload, proxy_entries): (OffloadSender, SharedProxyEntries),
+    ) -> Result<Self, Error> {
         // get values from config
         let proxied_prefix = *config.get_proxied_pfx();
         let proxy_type = *config.get_proxy_type();
         let address_mangling = *config.get_address_mangling();
         let rewrite_prefix = *config.get_dst_pfx();
         let (upstream_ifs, downstream_ifs) = get_ifaces_defined_by_config(&config);
+        let kernel_routes = config
+            .get_forwarded_ifaces()
+            .is_auto()
+            .then_some(kernel_routes);
+        let kernel_neighbors = (*config.get_neighbor_oracle() == NeighborOracle::Kernel
+            && proxy_type == Proxy::Forward)
+            .then_some(kernel_neighbors);
+        let autowire = (*config.get_autowire() && proxy_type == Proxy::Forward).then_some(autowire);
+        let offload = config.get_kernel_offload().then_some(offload);
+        let neigh_socket = match kernel_neighbors {
+            Some(_) => Some(NetlinkSocket::new(0)?),
+            None => None,
+        };
+        let hosts_file = match config.get_hosts_file() {
+            Some(path) => Some(FileMonitor::new(path)?),
+            None => None,
+        };
+        let leases_file = match config.get_leases() {
+            Some((path, format)) => Some((FileMonitor::new(path)?, *format)),
+            None => None,
+        };
         // generate local resources
-        let (mpsc_sender, mpsc_receiver) = mpsc::channel(MPSC_CAPACITY);
+        let (mpsc_sender, mpsc_receiver) = mpsc::channel(*config.get_queue_depth());
+        let (pending_sender, pending_receiver) = mpsc::channel(*config.get_queue_depth());
+        let (ifaces_sender, ifaces_receiver) =
+            watch::channel((upstream_ifs.clone(), downstream_ifs.clone()));
+        let (prefix_sender, prefix_receiver) = watch::channel(proxied_prefix);
         // packet sender
         let pkt_sender = PacketSender::new()?;
-        pkt_sender.set_multicast_hops_v6(255)?;
-        pkt_sender.set_unicast_hops_v6(255)?;
+        pkt_sender.set_multicast_hops_v6(ND_HOP_LIMIT)?;
+        pkt_sender.set_unicast_hops_v6(ND_HOP_LIMIT)?;
         // if everything goes as expected
         Ok(Self {
             proxy_type,
@@ -63,15 +267,55 @@ impl NDProxy {
             mpsc_receiver,
             mpsc_sender: Some(mpsc_sender),
             pkt_sender,
-            na_flag: 0,
+            na_flag: packets::na_flags(
+                *config.get_router(),
+                false,
+                *config.get_override_policy() == OverridePolicy::Always,
+            ),
+            dad_policy: *config.get_dad_policy(),
+            advertised_hwaddr: *config.get_advertised_hwaddr(),
+            na_delay: *config.get_na_delay(),
+            na_jitter: *config.get_na_jitter(),
+            delayed_nas: DelayedNAs::new(*config.get_na_queue_depth()),
+            observed_nas,
+            conflicts: Conflicts::new(*config.get_conflict_hold_time()),
             neighbors_cache,
+            negative_cache: NegativeCache::new(
+                *config.get_negative_cache_capacity(),
+                *config.get_negative_hold_down(),
+                *config.get_negative_max_hold_down(),
+            ),
+            static_hosts: config
+                .get_hosts()
+                .as_ref()
+                .map(|hosts| StaticHosts::new(hosts)),
+            config_hosts: config.get_hosts().clone().unwrap_or_default(),
+            hosts_file,
+            leases_file,
+            leases: HashMap::new(),
+            pending_solicitations,
+            pending_sender,
+            pending_receiver,
             upstream_ifs,
             downstream_ifs,
+            ifaces_sender: Some(ifaces_sender),
+            ifaces_receiver,
+            prefix_sender: Some(prefix_sender),
+            prefix_receiver,
+            kernel_routes,
+            kernel_neighbors,
+            neigh_socket,
+            autowire,
+            host_routes,
+            offload,
+            proxy_entries,
         })
     }
 
     pub async fn run(mut self) -> Result<(), Error> {
         drop(self.mpsc_sender.take());
+        drop(self.ifaces_sender.take());
+        drop(self.prefix_sender.take());
         warn!("NDProxy for {}: Start to work.", self.proxied_prefix);
         match self.proxy_type {
             Proxy::Static => self.run_static().await,
@@ -80,142 +324,1225 @@ impl NDProxy {
     }
 
     async fn run_static(mut self) -> Result<(), Error> {
-        while let Some((scope_id, tgt_addr, packet)) = self.mpsc_receiver.recv().await {
-            let macaddr = match self.upstream_ifs.get(&scope_id) {
-                Some(iface) => iface.get_hwaddr(),
-                None => continue,
-            };
-            let src_addr = unsafe { address_translation::construct_v6addr_unchecked(&packet[8..]) };
-            // TODO: randomly send to multicast addr
-            self.send_na_to_upstream(src_addr, *tgt_addr, macaddr, scope_id)
-                .await?
+        self.reload_hosts().await;
+        self.reload_leases().await;
+        self.offload_hosts().await;
+        loop {
+            tokio::select! {
+                received = self.mpsc_receiver.recv() => {
+                    let Some((scope_id, tgt_addr, packet)) = received else {
+                        break;
+                    };
+                    let macaddr = match self.upstream_ifs.get(&scope_id) {
+                        Some(iface) => *iface.get_hwaddr(),
+                        None => continue,
+                    };
+                    // only single hosts and leased targets are offloaded, not the targets of prefix-wide rules,
+                    // sub-prefixes or ranges, or scanning them would fill the proxy table of the kernel
+                    let lifetime = match &self.static_hosts {
+                        None if self.proxied_prefix.prefix_len() == 128 => {
+                            Some(ProxyLifetime::Permanent)
+                        }
+                        None => None,
+                        Some(hosts) if hosts.is_host(*tgt_addr) => Some(ProxyLifetime::Permanent),
+                        Some(hosts) => match self.lease(*tgt_addr) {
+                            Some(lifetime) => Some(lifetime),
+                            // the sub-prefixes and ranges of hosts are answered by me
+                            None if hosts.contains(*tgt_addr) => None,
+                            None => {
+                                trace!(
+                                    "NDProxy for {}: {} is not one of the hosts, ignore it.",
+                                    self.proxied_prefix, tgt_addr
+                                );
+                                continue;
+                            }
+                        },
+                    };
+                    let src_addr =
+                        unsafe { address_translation::construct_v6addr_unchecked(&packet[8..]) };
+                    let unicast_ns = !unsafe {
+                        address_translation::construct_v6addr_unchecked(&packet[24..])
+                    }
+                    .is_multicast();
+                    if src_addr.is_unspecified() && self.dad_policy == DadPolicy::Silent {
+                        trace!(
+                            "NDProxy for {}: Ignore the DAD NS for {}.",
+                            self.proxied_prefix, tgt_addr
+                        );
+                        continue;
+                    }
+                    if self.conflicted(scope_id, *tgt_addr, macaddr).await {
+                        continue;
+                    }
+                    if let Some(lifetime) = lifetime
+                        && self.offload(scope_id, *tgt_addr, lifetime).await
+                    {
+                        continue;
+                    }
+                    self.answer_upstream(src_addr, *tgt_addr, macaddr, scope_id, unicast_ns)
+                        .await
+                }
+                // pending forever without delayed NAs
+                () = delayed_na_due(&self.delayed_nas) => self.send_delayed_nas().await,
+                // the branch is disabled once IfaceMonitor has gone
+                Ok(()) = self.ifaces_receiver.changed() => {
+                    self.update_ifaces();
+                    self.offload_hosts().await;
+                }
+                // the branch is disabled once IfaceMonitor has gone
+                Ok(()) = self.prefix_receiver.changed() => {
+                    self.update_prefix().await;
+                    self.offload_hosts().await;
+                }
+                // pending forever without a hosts file
+                changed = file_changed(self.hosts_file.as_mut()) => match changed {
+                    Ok(()) => {
+                        self.reload_hosts().await;
+                        self.offload_hosts().await;
+                    }
+                    Err(e) => {
+                        error!(
+                            "NDProxy for {}: _{:?}_ Failed to follow the hosts file, keep the current hosts.",
+                            self.proxied_prefix, e
+                        );
+                        self.hosts_file = None;
+                    }
+                },
+                // pending forever without a lease file
+                changed = file_changed(self.leases_file.as_mut().map(|(file, _)| file)) => {
+                    self.leases_changed(changed).await
+                }
+            }
         }
         Err(Error::MpscRecvNone())
     }
 
-    async fn run_forward(mut self) -> Result<(), Error> {
-        while let Some((scope_id, tgt_addr, packet)) = self.mpsc_receiver.recv().await {
-            // I will not process the pkt,
-            // if the scope id does not show up in upstream_ifs
-            let macaddr = match self.upstream_ifs.get(&scope_id) {
-                Some(iface) => iface.get_hwaddr().to_owned(),
-                None => continue,
-            };
+    /// read the hosts file again, and swap the hosts if it is fine
+    async fn reload_hosts(&mut self) {
+        let Some(hosts_file) = &self.hosts_file else {
+            return;
+        };
+        let loaded = match hosts::reload_hosts_file(
+            &mut self.static_hosts,
+            &self.config_hosts,
+            hosts_file.get_path(),
+            &self.proxied_prefix,
+        ) {
+            Ok(v) => v,
+            Err(e) => {
+                error!(
+                    "NDProxy for {}: Failed to load {}, keep the current hosts: {}.",
+                    self.proxied_prefix,
+                    hosts_file.get_path().display(),
+                    e
+                );
+                return;
+            }
+        };
+        info!(
+            "NDProxy for {}: Loaded {} prefixes of hosts from {}.",
+            self.proxied_prefix,
+            loaded,
+            hosts_file.get_path().display()
+        );
+        // the kernel should not answer for the hosts that have gone
+        self.withdraw_offloaded(|addr, lifetime| {
+            !matches!(lifetime, ProxyLifetime::Lease(_))
+                && !self
+                    .static_hosts
+                    .as_ref()
+                    .is_some_and(|static_hosts| static_hosts.contains(addr))
+        })
+        .await;
+    }
 
-            // rewrite the target address if needed
-            let rewrited_addr = match self.address_mangling {
-                AddressMangling::Netmap => {
-                    address_translation::netmapv6(*tgt_addr, &self.rewrite_prefix)
-                }
-                AddressMangling::Npt => address_translation::nptv6(
-                    self.proxied_prefix_csum,
-                    self.rewrite_prefix_csum,
-                    *tgt_addr,
-                    &self.rewrite_prefix,
-                ),
-                AddressMangling::Nochange => *tgt_addr,
-            };
+    /// the lease file has changed, or failed to be followed
+    async fn leases_changed(&mut self, changed: Result<(), Error>) {
+        match changed {
+            Ok(()) => self.reload_leases().await,
+            Err(e) => {
+                error!(
+                    "NDProxy for {}: _{:?}_ Failed to follow the lease file, keep the current leases.",
+                    self.proxied_prefix, e
+                );
+                self.leases_file = None;
+            }
+        }
+    }
 
-            // send unicast NS anyways
-            self.forward_ns_to_downstream(rewrited_addr, rewrited_addr, scope_id)
-                .await?;
+    /// read the lease file again, the current leases are kept if it cannot be read
+    async fn reload_leases(&mut self) {
+        let Some((leases_file, format)) = &self.leases_file else {
+            return;
+        };
+        let content = match std::fs::read_to_string(leases_file.get_path()) {
+            Ok(v) => v,
+            Err(e) => {
+                error!(
+                    "NDProxy for {}: _{:?}_ Failed to read {}, keep the current leases.",
+                    self.proxied_prefix,
+                    e,
+                    leases_file.get_path().display()
+                );
+                return;
+            }
+        };
+        let (leases, skipped) = leases::parse_leases(*format, &content);
+        if skipped > 0 {
+            warn!(
+                "NDProxy for {}: {} entries of {} are not understood, skip them.",
+                self.proxied_prefix,
+                skipped,
+                leases_file.get_path().display()
+            );
+        }
+        // the leased addresses are the local ones, and the ended leases are dropped
+        let (now, wall_clock) = (Instant::now(), SystemTime::now());
+        let leases: HashMap<_, _> = leases
+            .into_iter()
+            .filter(|(local_addr, _)| self.rewrite_prefix.contains(local_addr))
+            .filter_map(|(local_addr, end)| {
+                let end = match end {
+                    Some(secs) => Some(
+                        now + (UNIX_EPOCH + Duration::from_secs(secs))
+                            .duration_since(wall_clock)
+                            .ok()?,
+                    ),
+                    None => None,
+                };
+                Some((self.unrewrite(local_addr), end))
+            })
+            .collect();
+        info!(
+            "NDProxy for {}: {} active leases in {}.",
+            self.proxied_prefix,
+            leases.len(),
+            leases_file.get_path().display()
+        );
+        self.leases = leases;
+        // the kernel should not answer for the leases that have gone
+        self.withdraw_offloaded(|addr, lifetime| {
+            matches!(lifetime, ProxyLifetime::Lease(_)) && self.lease(addr).is_none()
+        })
+        .await;
+    }
+
+    /// the lifetime of the active DHCPv6 lease of the target
+    fn lease(&self, tgt_addr: Ipv6Addr) -> Option<ProxyLifetime> {
+        match self.leases.get(&tgt_addr)? {
+            Some(end) if *end <= Instant::now() => None,
+            end => Some(ProxyLifetime::Lease(*end)),
+        }
+    }
+
+    /// remove the proxy entries of my upstream interfaces that are gone(address, lifetime),
+    /// they expire on the next check of ProxyOffload
+    async fn withdraw_offloaded<F>(&self, gone: F)
+    where
+        F: Fn(Ipv6Addr, ProxyLifetime) -> bool,
+    {
+        let withdrawn: Vec<_> = self
+            .proxy_entries
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|((scope_id, addr), entry)| {
+                self.upstream_ifs.contains_key(scope_id)
+                    && self.proxied_prefix.contains(addr)
+                    && gone(*addr, entry.get_lifetime())
+            })
+            .map(|(key, _)| *key)
+            .collect();
+        for (scope_id, addr) in withdrawn {
+            self.offload(scope_id, addr, ProxyLifetime::Idle(Duration::ZERO))
+                .await;
+        }
+    }
 
-            // get the cache
-            match self
-                .downstream_ifs
-                .keys()
-                .map(|nei_scope_id| self.neighbors_cache.get(&(*nei_scope_id, rewrited_addr)))
-                .any(|res| res.is_some())
+    /// interfaces have changed
+    fn update_ifaces(&mut self) {
+        (self.upstream_ifs, self.downstream_ifs) = self.ifaces_receiver.borrow_and_update().clone();
+        info!(
+            "NDProxy for {}: Upstream interfaces: {:?}, downstream interfaces: {:?}.",
+            self.proxied_prefix,
+            self.upstream_ifs
+                .values()
+                .map(|iface| iface.get_name())
+                .collect::<Vec<_>>(),
+            self.downstream_ifs
+                .values()
+                .map(|iface| iface.get_name())
+                .collect::<Vec<_>>(),
+        );
+    }
+
+    /// the prefix of the interface that I follow has changed,
+    /// the proxy entries and the routes of the old one are withdrawn first
+    async fn update_prefix(&mut self) {
+        let old = self.proxied_prefix;
+        self.withdraw_offloaded(|_, _| true).await;
+        // the rewritten targets stay in the local prefix, and so do their routes
+        if self.autowire.is_some() && self.address_mangling == AddressMangling::Nochange {
+            let downstream_ifs = &self.downstream_ifs;
+            if let Err(e) = autowire::unwire_where(&self.host_routes, |addr, scope_id| {
+                downstream_ifs.contains_key(&scope_id) && old.contains(&addr)
+            })
+            .await
             {
-                true => {
+                warn!(
+                    "NDProxy for {}: _{:?}_ Failed to remove the routes of the old prefix.",
+                    old, e
+                );
+            }
+        }
+        self.proxied_prefix = *self.prefix_receiver.borrow_and_update();
+        self.proxied_prefix_csum = address_translation::pfx_csum(&self.proxied_prefix);
+        info!("NDProxy for {}: Was {} before.", self.proxied_prefix, old);
+    }
+
+    async fn run_forward(mut self) -> Result<(), Error> {
+        self.reload_leases().await;
+        loop {
+            tokio::select! {
+                received = self.mpsc_receiver.recv() => match received {
+                    Some((scope_id, tgt_addr, packet)) => {
+                        self.proxy_ns_forward(scope_id, *tgt_addr, &packet).await
+                    }
+                    None => break,
+                },
+                // pending_receiver never closes, because I am holding pending_sender
+                Some((scope_id, ns_origin, tgt_addr)) = self.pending_receiver.recv() => {
+                    // the NS is answered by a NA from downstream, send back the proxied NA
+                    let macaddr = match self.upstream_ifs.get(&scope_id) {
+                        Some(iface) => iface.get_hwaddr().to_owned(),
+                        None => continue,
+                    };
+                    if self.conflicted(scope_id, tgt_addr, macaddr).await {
+                        continue;
+                    }
+                    let rewrited_addr = self.rewrite(tgt_addr);
+                    if let Some(nei_scope_id) = self.reachable_iface(rewrited_addr) {
+                        self.wire(rewrited_addr, nei_scope_id).await;
+                        let lifetime = ProxyLifetime::Neighbor(nei_scope_id, rewrited_addr);
+                        self.offload(scope_id, tgt_addr, lifetime).await;
+                    }
+                    // the destination of the NS is not known here, so the MAC is advertised anyway
+                    self.answer_upstream(ns_origin, tgt_addr, macaddr, scope_id, false)
+                        .await
+                }
+                // pending forever without delayed NAs
+                () = delayed_na_due(&self.delayed_nas) => self.send_delayed_nas().await,
+                // the branch is disabled once IfaceMonitor has gone
+                Ok(()) = self.ifaces_receiver.changed() => self.update_ifaces(),
+                // the branch is disabled once IfaceMonitor has gone
+                Ok(()) = self.prefix_receiver.changed() => self.update_prefix().await,
+                // pending forever without a lease file
+                changed = file_changed(self.leases_file.as_mut().map(|(file, _)| file)) => {
+                    self.leases_changed(changed).await
+                }
+            }
+        }
+        Err(Error::MpscRecvNone())
+    }
+
+    async fn proxy_ns_forward(&mut self, scope_id: u32, tgt_addr: Ipv6Addr, packet: &[u8]) {
+        // I will not process the pkt,
+        // if the scope id does not show up in upstream_ifs
+        let macaddr = match self.upstream_ifs.get(&scope_id) {
+            Some(iface) => iface.get_hwaddr().to_owned(),
+            None => return,
+        };
+        let ns_origin = unsafe { address_translation::construct_v6addr_unchecked(&packet[8..]) };
+        let unicast_ns = !unsafe { address_translation::construct_v6addr_unchecked(&packet[24..]) }
+            .is_multicast();
+        let dad = ns_origin.is_unspecified();
+
+        // rewrite the target address if needed
+        let rewrited_addr = self.rewrite(tgt_addr);
+
+        // get the cache, neighbors that failed probing are not proxied,
+        // and an active DHCPv6 lease tells that the target is there, no need to ask downstream
+        let in_use = match self.reachable_iface(rewrited_addr) {
+            Some(nei_scope_id) => Some(ProxyLifetime::Neighbor(nei_scope_id, rewrited_addr)),
+            None => self.lease(tgt_addr),
+        };
+        match ns_action(dad, self.dad_policy, in_use.is_some()) {
+            NSAction::Drop => {
+                trace!(
+                    "NDProxy for {}: Let the DAD for {} go on, by the {:?} policy.",
+                    self.proxied_prefix, tgt_addr, self.dad_policy
+                );
+            }
+            NSAction::Answer => {
+                // answered only when in use
+                let Some(lifetime) = in_use else {
+                    return;
+                };
+                match lifetime {
                     // if the neighbors exist in cache, send back the proxied NA
-                    self.send_na_to_upstream(
-                        unsafe { address_translation::construct_v6addr_unchecked(&packet[8..]) },
-                        *tgt_addr,
-                        &macaddr,
-                        scope_id,
-                    )
-                    .await?
+                    ProxyLifetime::Neighbor(nei_scope_id, _) => {
+                        self.negative_cache.forget(&rewrited_addr);
+                        self.wire(rewrited_addr, nei_scope_id).await;
+                    }
+                    _ => trace!(
+                        "NDProxy for {}: {} is leased, answer it right away.",
+                        self.proxied_prefix, tgt_addr
+                    ),
+                }
+                if self.conflicted(scope_id, tgt_addr, macaddr).await {
+                    return;
+                }
+                if self.offload(scope_id, tgt_addr, lifetime).await {
+                    return;
+                }
+                self.answer_upstream(ns_origin, tgt_addr, macaddr, scope_id, unicast_ns)
+                    .await
+            }
+            NSAction::Forward => {
+                // do not bother downstream interfaces with targets that do not exist
+                let now = Instant::now();
+                if self.negative_cache.held(&rewrited_addr, now) {
+                    trace!(
+                        "NDProxy for {}: {} is held down by the negative cache.",
+                        self.proxied_prefix, rewrited_addr
+                    );
+                    return;
                 }
-                false => {
-                    // send multicast NS if the neighbor does not exist, and increase the possibility to find it
-                    self.forward_ns_to_downstream(
-                        address_translation::gen_solicited_node_multicast_address(&rewrited_addr),
+                if self.resolution_failed(rewrited_addr, scope_id)
+                    && let Some(hold) = self.negative_cache.record_failure(rewrited_addr, now)
+                {
+                    info!(
+                        "NDProxy for {}: {} is unreachable, hold it down for {:?}. ({} NSes suppressed so far)",
+                        self.proxied_prefix,
                         rewrited_addr,
-                        scope_id,
-                    )
-                    .await?
+                        hold,
+                        self.negative_cache.get_hits()
+                    );
+                    return;
                 }
+                // remember the NS, so that it can be answered once the neighbor shows up
+                self.add_pending_ns(rewrited_addr, (scope_id, ns_origin, tgt_addr));
+                if self.kernel_neighbors.is_some() {
+                    return self.resolve_by_kernel(rewrited_addr, scope_id).await;
+                }
+                // send multicast NS if the neighbor does not exist,
+                // NeighborProber will retransmit it if nobody answers
+                self.forward_ns_to_downstream(
+                    address_translation::gen_solicited_node_multicast_address(&rewrited_addr),
+                    rewrited_addr,
+                    scope_id,
+                )
+                .await
             }
         }
-        Err(Error::MpscRecvNone())
     }
 
-    /// construct a NA packet, and send it to upstream
+    /// the local address of the proxied address
+    fn rewrite(&self, tgt_addr: Ipv6Addr) -> Ipv6Addr {
+        match self.address_mangling {
+            AddressMangling::Netmap => {
+                address_translation::netmapv6(tgt_addr, &self.rewrite_prefix)
+            }
+            AddressMangling::Npt => address_translation::nptv6(
+                self.proxied_prefix_csum,
+                self.rewrite_prefix_csum,
+                tgt_addr,
+                &self.rewrite_prefix,
+            ),
+            AddressMangling::Nochange => tgt_addr,
+        }
+    }
+
+    /// the proxied address of the local address
+    fn unrewrite(&self, local_addr: Ipv6Addr) -> Ipv6Addr {
+        match self.address_mangling {
+            AddressMangling::Netmap => {
+                address_translation::netmapv6(local_addr, &self.proxied_prefix)
+            }
+            AddressMangling::Npt => address_translation::nptv6(
+                self.rewrite_prefix_csum,
+                self.proxied_prefix_csum,
+                local_addr,
+                &self.proxied_prefix,
+            ),
+            AddressMangling::Nochange => local_addr,
+        }
+    }
+
+    /// the downstream interface where the target is reachable
+    fn reachable_iface(&self, rewrited_addr: Ipv6Addr) -> Option<u32> {
+        self.downstream_ifs
+            .keys()
+            .find(|nei_scope_id| self.neighbor_reachable(**nei_scope_id, rewrited_addr))
+            .copied()
+    }
+
+    /// route the confirmed target to its downstream interface, if autowire is enabled
+    async fn wire(&self, rewrited_addr: Ipv6Addr, nei_scope_id: u32) {
+        if let Some(autowire) = &self.autowire
+            && let Err(e) = autowire.send((rewrited_addr, nei_scope_id)).await
+        {
+            debug!(
+                "NDProxy for {}: _{:?}_ Autowire has gone.",
+                self.proxied_prefix, e
+            );
+        }
+    }
+
+    /// let the kernel answer NSes for the proxied address, if kernel offload is enabled,
+    /// returns whether the kernel is answering them already
+    async fn offload(&self, scope_id: u32, tgt_addr: Ipv6Addr, lifetime: ProxyLifetime) -> bool {
+        let Some(offload) = &self.offload else {
+            return false;
+        };
+        let offloaded = self
+            .proxy_entries
+            .lock()
+            .unwrap()
+            .get(&(scope_id, tgt_addr))
+            .is_some();
+        if let Err(e) = offload.send((scope_id, tgt_addr, lifetime)).await {
+            debug!(
+                "NDProxy for {}: _{:?}_ ProxyOffload has gone.",
+                self.proxied_prefix, e
+            );
+        }
+        offloaded
+    }
+
+    /// a static /128 proxied_prefix, or a single address in hosts, is an explicit host,
+    /// offload it to every upstream interface
+    async fn offload_hosts(&self) {
+        let hosts = match &self.static_hosts {
+            Some(static_hosts) => static_hosts.hosts().iter().copied().collect(),
+            // a prefix taken from an interface is :: until the interface has an address
+            None if self.proxied_prefix.prefix_len() == 128
+                && !self.proxied_prefix.addr().is_unspecified() =>
+            {
+                vec![self.proxied_prefix.addr()]
+            }
+            None => return,
+        };
+        for scope_id in self.upstream_ifs.keys() {
+            for host in hosts.iter() {
+                self.offload(*scope_id, *host, ProxyLifetime::Permanent)
+                    .await;
+            }
+        }
+    }
+
+    /// ask the neighbor oracle whether the target is reachable on the downstream interface
+    fn neighbor_reachable(&self, scope_id: u32, rewrited_addr: Ipv6Addr) -> bool {
+        match &self.kernel_neighbors {
+            Some(neighbors) => neighbors
+                .lock()
+                .unwrap()
+                .get(&(scope_id, rewrited_addr))
+                .is_some_and(|neighbor| neighbor.is_usable()),
+            None => self.neighbors_cache.reachable(&(scope_id, rewrited_addr)),
+        }
+    }
+
+    /// whether the address resolution failed on every downstream interface
+    fn resolution_failed(&self, rewrited_addr: Ipv6Addr, origin_scope_id: u32) -> bool {
+        let mut entries = self
+            .downstream_ifs
+            .keys()
+            .filter(|id| **id != origin_scope_id)
+            .map(|id| match &self.kernel_neighbors {
+                Some(neighbors) => neighbors
+                    .lock()
+                    .unwrap()
+                    .get(&(*id, rewrited_addr))
+                    .is_some_and(|neighbor| neighbor.is_failed()),
+                None => self
+                    .neighbors_cache
+                    .get(&(*id, rewrited_addr))
+                    .is_some_and(|entry| entry.get_state() == NeighborState::Failed),
+            })
+            .peekable();
+        entries.peek().is_some() && entries.all(|failed| failed)
+    }
+
+    /// wait for the NA of rewrited_addr on every downstream interface
+    fn add_pending_ns(&self, rewrited_addr: Ipv6Addr, pending_ns: PendingNS) {
+        add_pending_ns(
+            &self.pending_solicitations,
+            self.downstream_ifs.keys().copied(),
+            rewrited_addr,
+            pending_ns,
+            &self.pending_sender,
+        );
+    }
+
+    /// the link-layer address of the downstream neighbor, if it is known
+    fn neighbor_hwaddr(&self, tgt_addr: Ipv6Addr) -> Option<MacAddr> {
+        let rewrited_addr = self.rewrite(tgt_addr);
+        let key = (self.reachable_iface(rewrited_addr)?, rewrited_addr);
+        match &self.kernel_neighbors {
+            Some(neighbors) => neighbors.lock().unwrap().get(&key)?.hwaddr,
+            None => *self.neighbors_cache.get(&key)?.get_hwaddr(),
+        }
+    }
+
+    /// the link-layer address I advertise for the target
+    ///
+    /// iface_hwaddr: the hwaddr of the upstream interface
+    fn tgt_hwaddr(&self, tgt_addr: Ipv6Addr, iface_hwaddr: MacAddr) -> MacAddr {
+        advertised_hwaddr(self.advertised_hwaddr, iface_hwaddr, || {
+            self.neighbor_hwaddr(tgt_addr)
+        })
+    }
+
+    /// whether the target has been advertised on the upstream interface with another MAC recently,
+    /// a real host owns it then, and I stop answering for it (and withdraw its proxy entry) for a while
+    async fn conflicted(
+        &mut self,
+        scope_id: u32,
+        tgt_addr: Ipv6Addr,
+        iface_hwaddr: MacAddr,
+    ) -> bool {
+        if self.conflicts.hold_time.is_zero() {
+            return false;
+        }
+        let key = (scope_id, tgt_addr);
+        let tgt_hwaddr = self.tgt_hwaddr(tgt_addr, iface_hwaddr);
+        let (conflicted, reported) =
+            self.conflicts
+                .check(key, self.observed_nas.get(&key), tgt_hwaddr, Instant::now());
+        if let Some(foreign_hwaddr) = reported {
+            warn!(
+                "NDProxy for {}: Address conflict on {}: {} is advertised at {} by someone else, and at {} by me, stop answering for it for {:?}.",
+                self.proxied_prefix,
+                self.upstream_ifs
+                    .get(&scope_id)
+                    .map_or("?", |iface| iface.get_name()),
+                tgt_addr,
+                foreign_hwaddr,
+                tgt_hwaddr,
+                self.conflicts.hold_time
+            );
+            let offloaded = self.proxy_entries.lock().unwrap().contains_key(&key);
+            if offloaded {
+                self.offload(scope_id, tgt_addr, ProxyLifetime::Idle(Duration::ZERO))
+                    .await;
+            }
+        }
+        conflicted
+    }
+
+    /// answer a NS from upstream, at once or after the delay of the rule
+    ///
+    /// iface_hwaddr: the hwaddr of the upstream interface
+    /// unicast_ns: whether the NS was sent to a unicast address
+    async fn answer_upstream(
+        &mut self,
+        ns_origin: Ipv6Addr,
+        tgt_addr: Ipv6Addr,
+        iface_hwaddr: MacAddr,
+        scope_id: u32,
+        unicast_ns: bool,
+    ) {
+        let tgt_hwaddr = tlla_option(
+            self.tgt_hwaddr(tgt_addr, iface_hwaddr),
+            iface_hwaddr,
+            unicast_ns,
+        );
+        if self.na_delay.is_zero() && self.na_jitter.is_zero() {
+            return self
+                .send_na_to_upstream(ns_origin, tgt_addr, tgt_hwaddr, scope_id)
+                .await;
+        }
+        let now = Instant::now();
+        let delay = self.na_delay + random_up_to(self.na_jitter);
+        trace!(
+            "NDProxy for {}: Answer for {} in {:?}.",
+            self.proxied_prefix, tgt_addr, delay
+        );
+        if let Some(dropped) = self.delayed_nas.push(DelayedNA {
+            due: now + delay,
+            queued: now,
+            scope_id,
+            ns_origin,
+            tgt_addr,
+            hwaddr: tgt_hwaddr,
+        }) {
+            debug!(
+                "NDProxy for {}: Too many delayed NAs, drop the one for {}.",
+                self.proxied_prefix, dropped.tgt_addr
+            );
+        }
+    }
+
+    /// send the delayed NAs that are due, unless their targets have been advertised by someone else
+    async fn send_delayed_nas(&mut self) {
+        let (due, cancelled) = self
+            .delayed_nas
+            .take_due(Instant::now(), &self.observed_nas);
+        for na in cancelled {
+            debug!(
+                "NDProxy for {}: {} has been advertised by someone else, cancel my NA.",
+                self.proxied_prefix, na.tgt_addr
+            );
+        }
+        for na in due {
+            self.send_na_to_upstream(na.ns_origin, na.tgt_addr, na.hwaddr, na.scope_id)
+                .await;
+        }
+    }
+
+    /// construct a NA packet, and send it to upstream, a failure is logged and the NA is dropped
     async fn send_na_to_upstream(
         &self,
         ns_origin: Ipv6Addr,
         proxied_addr: Ipv6Addr,
-        src_hwaddr: &MacAddr,
+        tgt_hwaddr: Option<MacAddr>,
         scope_id: u32,
-    ) -> Result<(), Error> {
+    ) {
+        let (dst_addr, na_pkt) =
+            match na_to_upstream(ns_origin, proxied_addr, tgt_hwaddr.as_ref(), self.na_flag) {
+                Ok(v) => v,
+                Err(e) => {
+                    error!(
+                        "NDProxy for {}: _{:?}_ Failed to construct the NA for {}.",
+                        self.proxied_prefix, e, proxied_addr
+                    );
+                    return;
+                }
+            };
         info!(
-            "NDProxy for {}: Send NA for {} to {} on interface {:?}",
+            "NDProxy for {}: Send NA for {} at {} to {} on interface {:?}",
             self.proxied_prefix,
             proxied_addr,
-            ns_origin,
+            tgt_hwaddr.map_or(String::from("the address cached upstream"), |hwaddr| {
+                hwaddr.to_string()
+            }),
+            dst_addr,
             self.upstream_ifs.get(&scope_id)
         );
-        // construct the NA packet
-        let na_pkt = packets::generate_NA_forwarded(
-            &Ipv6Addr::UNSPECIFIED,
-            &ns_origin,
-            &proxied_addr,
-            src_hwaddr,
-            self.na_flag,
-        )?;
         // send the packet via send_to()
-        self.pkt_sender
+        if let Err(e) = self
+            .pkt_sender
             .send_pkt_to(
                 na_pkt.packet(),
-                &SocketAddrV6::new(ns_origin, 0, 0, scope_id).into(),
+                &SocketAddrV6::new(dst_addr, 0, 0, scope_id).into(),
             )
-            .await?;
-        Ok(())
+            .await
+        {
+            warn!(
+                "NDProxy for {}: _{:?}_ Failed to send the NA for {} to {} on scope id {}.",
+                self.proxied_prefix, e, proxied_addr, dst_addr, scope_id
+            );
+        }
     }
 
-    /// discover neighbors on proxied (downstream) interfaces
+    /// discover neighbors on proxied (downstream) interfaces, a failure is logged and the NS is dropped
     async fn forward_ns_to_downstream(
         &mut self,
         dst_addr: Ipv6Addr,
         ns_tgt_addr: Ipv6Addr,
         origin_scope_id: u32,
-    ) -> Result<(), Error> {
+    ) {
         // logging
         trace!(
             "NDProxy for {}: Send Neighbour Solicition packet for {} to {}.",
             self.proxied_prefix, ns_tgt_addr, dst_addr
         );
         // send to every interested interface
-        for (id, iface) in self.downstream_ifs.iter() {
-            if *id == origin_scope_id {
+        for (id, iface) in self.interested_ifaces(ns_tgt_addr, origin_scope_id) {
+            // skip the interfaces where the address resolution is in progress
+            if !self.neighbors_cache.solicit((id, ns_tgt_addr)) {
                 continue;
-            };
+            }
 
-            // send unicast NS packet anyways
-            self.pkt_sender
-                .send_pkt_to(
-                    packets::generate_NS_packet(
-                        iface.get_link_addr(),
-                        &dst_addr,
-                        &ns_tgt_addr,
-                        Some(iface.get_hwaddr()),
-                    )?
-                    .packet(),
-                    &SocketAddrV6::new(dst_addr, 0, 0, *id).into(),
-                )
-                .await?;
+            if let Err(e) = self.send_ns(&iface, dst_addr, ns_tgt_addr).await {
+                warn!(
+                    "NDProxy for {}: _{:?}_ Failed to send NS for {} to {} on scope id {}.",
+                    self.proxied_prefix, e, ns_tgt_addr, dst_addr, id
+                );
+                continue;
+            }
+            // send unicast NS anyways, through the route of the target,
+            // the kernel resolves it on the way, in case my multicast NS is missed
+            if dst_addr != ns_tgt_addr
+                && let Err(e) = self.send_ns(&iface, ns_tgt_addr, ns_tgt_addr).await
+            {
+                debug!(
+                    "NDProxy for {}: _{:?}_ Failed to send unicast NS for {} on scope id {}.",
+                    self.proxied_prefix, e, ns_tgt_addr, id
+                );
+            }
         }
+    }
+
+    /// construct a NS packet, and send it out of iface
+    async fn send_ns(
+        &self,
+        iface: &NDInterface,
+        dst_addr: Ipv6Addr,
+        ns_tgt_addr: Ipv6Addr,
+    ) -> Result<(), Error> {
+        self.pkt_sender
+            .send_pkt_to(
+                packets::generate_NS_packet(
+                    iface.get_link_addr(),
+                    &dst_addr,
+                    &ns_tgt_addr,
+                    Some(iface.get_hwaddr()),
+                )?
+                .packet(),
+                &SocketAddrV6::new(dst_addr, 0, 0, *iface.get_scope_id()).into(),
+            )
+            .await?;
         Ok(())
     }
+
+    /// ask the kernel to resolve the target on downstream interfaces,
+    /// NeighMonitor will wake me up once it is resolved
+    async fn resolve_by_kernel(&mut self, rewrited_addr: Ipv6Addr, origin_scope_id: u32) {
+        for (id, _) in self.interested_ifaces(rewrited_addr, origin_scope_id) {
+            // skip the interfaces where the address resolution is in progress
+            if self
+                .kernel_neighbors
+                .as_ref()
+                .and_then(|neighbors| neighbors.lock().unwrap().get(&(id, rewrited_addr)))
+                .is_some_and(|neighbor| neighbor.state & libc::NUD_INCOMPLETE != 0)
+            {
+                continue;
+            }
+            let Some(socket) = self.neigh_socket.as_mut() else {
+                return;
+            };
+            trace!(
+                "NDProxy for {}: Ask the kernel to resolve {} on scope id {}.",
+                self.proxied_prefix, rewrited_addr, id
+            );
+            // NTF_USE makes the kernel start the address resolution, as sending a packet would
+            let msg = NetlinkMessage::new(
+                libc::RTM_NEWNEIGH,
+                (libc::NLM_F_CREATE | libc::NLM_F_REPLACE) as u16,
+                &ndmsg(id, libc::NUD_NONE, libc::NTF_USE),
+            )
+            .push_attr(libc::NDA_DST, &rewrited_addr.octets());
+            if let Err(e) = socket.request(msg).await {
+                debug!(
+                    "NDProxy for {}: _{:?}_ The kernel refused to resolve {}.",
+                    self.proxied_prefix, e, rewrited_addr
+                );
+            }
+        }
+    }
+
+    /// the downstream interfaces that the target may live on
+    fn interested_ifaces(
+        &self,
+        tgt_addr: Ipv6Addr,
+        origin_scope_id: u32,
+    ) -> Vec<(u32, NDInterface)> {
+        // "auto": ask the routing table which interface is interested
+        let routed_scope_id = self
+            .kernel_routes
+            .as_ref()
+            .map(|routes| routes.lock().unwrap().lookup(tgt_addr));
+        if routed_scope_id == Some(None) {
+            trace!(
+                "NDProxy for {}: No route to {}.",
+                self.proxied_prefix, tgt_addr
+            );
+        }
+        self.downstream_ifs
+            .iter()
+            .filter(|(id, _)| **id != origin_scope_id)
+            .filter(|(id, _)| routed_scope_id.is_none_or(|routed| routed == Some(**id)))
+            .map(|(id, iface)| (*id, iface.clone()))
+            .collect()
+    }
+}
+
+/// wait for the file to change, forever if there is none
+async fn file_changed(file: Option<&mut FileMonitor>) -> Result<(), Error> {
+    match file {
+        Some(file) => file.changed().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// what a forward rule does with a NS
+#[derive(Debug, PartialEq, Eq)]
+enum NSAction {
+    /// answer it for the target in use
+    Answer,
+    /// solicit the target downstream
+    Forward,
+    /// let the DAD go on
+    Drop,
+}
+
+/// the DAD NSes (dad) follow the policy of the rule, in_use tells whether the target is a confirmed neighbor or leased
+fn ns_action(dad: bool, dad_policy: DadPolicy, in_use: bool) -> NSAction {
+    match (dad, dad_policy, in_use) {
+        (true, DadPolicy::Silent, _) => NSAction::Drop,
+        (_, _, true) => NSAction::Answer,
+        // the DAD goes on for the targets not known to be in use, unless it is forwarded
+        (true, DadPolicy::Defend, false) => NSAction::Drop,
+        (_, _, false) => NSAction::Forward,
+    }
+}
+
+/// the link-layer address advertised for a target, neighbor_hwaddr is asked for the "neighbor" policy only,
+/// and the upstream interface is advertised if the neighbor is unknown
+fn advertised_hwaddr(
+    policy: AdvertisedHwaddr,
+    iface_hwaddr: MacAddr,
+    neighbor_hwaddr: impl FnOnce() -> Option<MacAddr>,
+) -> MacAddr {
+    match policy {
+        AdvertisedHwaddr::Iface => iface_hwaddr,
+        AdvertisedHwaddr::Fixed(hwaddr) => hwaddr,
+        AdvertisedHwaddr::Neighbor => neighbor_hwaddr().unwrap_or(iface_hwaddr),
+    }
+}
+
+/// the Target Link-Layer Address option of the NA, RFC 4861 section 7.2.4:
+/// it is left out for a NS to a unicast address, as the solicitor has cached the MAC it reached me at,
+/// unless another MAC than the one of the upstream interface is advertised
+fn tlla_option(tgt_hwaddr: MacAddr, iface_hwaddr: MacAddr, unicast_ns: bool) -> Option<MacAddr> {
+    (!unicast_ns || tgt_hwaddr != iface_hwaddr).then_some(tgt_hwaddr)
+}
+
+/// the NA answering a NS from ns_origin, and its destination,
+/// RFC 4861 section 7.2.4, the NA for a DAD NS is multicast to all-nodes, and is not solicited
+fn na_to_upstream<'a>(
+    ns_origin: Ipv6Addr,
+    proxied_addr: Ipv6Addr,
+    tgt_hwaddr: Option<&MacAddr>,
+    na_flag: u8,
+) -> Result<(Ipv6Addr, NeighborAdvertPacket<'a>), Error> {
+    let (dst_addr, na_flag) = match ns_origin.is_unspecified() {
+        true => (ALL_NODES, na_flag),
+        false => (ns_origin, na_flag | NeighborAdvertFlags::Solicited),
+    };
+    let na_pkt = packets::generate_NA_forwarded(
+        &Ipv6Addr::UNSPECIFIED,
+        &dst_addr,
+        &proxied_addr,
+        tgt_hwaddr,
+        na_flag,
+    )?;
+    Ok((dst_addr, na_pkt))
+}
+
+/// wait for the NA of rewrited_addr on the downstream interfaces but the one the NS came from,
+/// the NS is handed back through sender once the target shows up
+fn add_pending_ns(
+    pending_solicitations: &PendingSolicitations,
+    downstream_ids: impl Iterator<Item = u32>,
+    rewrited_addr: Ipv6Addr,
+    pending_ns: PendingNS,
+    sender: &PendingNSSender,
+) {
+    for id in downstream_ids {
+        if id == pending_ns.0 {
+            continue;
+        };
+        let key = (id, rewrited_addr);
+        let mut waiters = pending_solicitations.get(&key).unwrap_or_default();
+        // upstream may retransmit the same NS, only answer it once
+        if waiters
+            .iter()
+            .any(|(ns, waiter)| *ns == pending_ns && waiter.same_channel(sender))
+        {
+            continue;
+        }
+        waiters.push((pending_ns, sender.clone()));
+        pending_solicitations.set(key, waiters, None);
+    }
+}
+
+/// a neighbor on downstream has shown up, answer the NSes waiting for it
+pub async fn answer_pending_solicitations(
+    pending_solicitations: &PendingSolicitations,
+    key: (u32, Ipv6Addr),
+) {
+    if pending_solicitations.get(&key).is_none() {
+        return;
+    }
+    for (pending_ns, sender) in pending_solicitations.remove(&key).unwrap_or_default() {
+        if let Err(e) = sender.send(pending_ns).await {
+            debug!(
+                "_{:?}_ The NDProxy waiting for 📢{}📢 on scope id {} has gone.",
+                e, key.1, key.0
+            );
+        }
+    }
+}
+
+/// wait for the first delayed NA to be due
+async fn delayed_na_due(delayed_nas: &DelayedNAs) {
+    match delayed_nas.next_due() {
+        Some(due) => tokio::time::sleep_until(due.into()).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// the entries of pending NSes and observed NAs expire by themselves, but their memory has to be reclaimed
+pub async fn expire_cache<K, V>(cache: Arc<Cache<K, V>>, ttl: Duration) -> Result<(), Error>
+where
+    K: Eq + std::hash::Hash + Clone,
+    V: Clone,
+{
+    let mut interval = tokio::time::interval(ttl);
+    loop {
+        interval.tick().await;
+        cache.remove_expired();
+    }
+}
+
+#[test]
+fn test_delayed_nas() {
+    let now = Instant::now();
+    let later = |ms| now + Duration::from_millis(ms);
+    let na = |tgt: &str, due| DelayedNA {
+        due,
+        queued: now,
+        scope_id: 1,
+        ns_origin: "fe80::1".parse().unwrap(),
+        tgt_addr: tgt.parse().unwrap(),
+        hwaddr: Some(MacAddr::new(0x02, 0, 0, 0, 0, 1)),
+    };
+    let observed_nas: ObservedNAs = Arc::new(Cache::new(None));
+    let mut delayed_nas = DelayedNAs::new(2);
+
+    // an NA waits for its delay, a retransmitted NS is not answered twice
+    assert!(delayed_nas.push(na("2001:db8::1", later(500))).is_none());
+    assert!(delayed_nas.push(na("2001:db8::1", later(100))).is_none());
+    assert_eq!(delayed_nas.next_due(), Some(later(500)));
+    let (due, cancelled) = delayed_nas.take_due(later(499), &observed_nas);
+    assert!(due.is_empty() && cancelled.is_empty());
+    let (due, cancelled) = delayed_nas.take_due(later(500), &observed_nas);
+    assert_eq!(due.len(), 1);
+    assert!(cancelled.is_empty());
+    assert_eq!(delayed_nas.next_due(), None);
+
+    // cancelled if the target answers first
+    delayed_nas.push(na("2001:db8::2", later(500)));
+    delayed_nas.push(na("2001:db8::3", later(500)));
+    observed_nas.set(
+        (1, "2001:db8::2".parse().unwrap()),
+        (later(200), Some(MacAddr::new(0x02, 0, 0, 0, 0, 2))),
+        None,
+    );
+    let (due, cancelled) = delayed_nas.take_due(later(500), &observed_nas);
+    assert_eq!(
+        due.iter().map(|na| na.tgt_addr).collect::<Vec<_>>(),
+        ["2001:db8::3".parse::<Ipv6Addr>().unwrap()]
+    );
+    assert_eq!(
+        cancelled.iter().map(|na| na.tgt_addr).collect::<Vec<_>>(),
+        ["2001:db8::2".parse::<Ipv6Addr>().unwrap()]
+    );
+
+    // the oldest one is dropped beyond the depth
+    delayed_nas.push(na("2001:db8::4", later(300)));
+    delayed_nas.push(na("2001:db8::5", later(200)));
+    let dropped = delayed_nas.push(na("2001:db8::6", later(100)));
+    assert_eq!(
+        dropped.map(|na| na.tgt_addr),
+        Some("2001:db8::4".parse().unwrap())
+    );
+    assert_eq!(delayed_nas.next_due(), Some(later(100)));
+}
+
+#[tokio::test]
+async fn test_pending_solicitations() {
+    let pending_solicitations: PendingSolicitations =
+        Arc::new(Cache::new(Some(Duration::from_millis(100))));
+    let (sender, mut receiver) = mpsc::channel(4);
+    let tgt_addr: Ipv6Addr = "2001:db8::1".parse().unwrap();
+    let pending_ns = (1, "fe80::1".parse().unwrap(), tgt_addr);
+
+    // waiting on the downstream interfaces 2 and 3, not on 1 that the NS came from,
+    // and the retransmitted NS is answered once
+    for _ in 0..2 {
+        add_pending_ns(
+            &pending_solicitations,
+            [1, 2, 3].into_iter(),
+            tgt_addr,
+            pending_ns,
+            &sender,
+        );
+    }
+    assert!(pending_solicitations.get(&(1, tgt_addr)).is_none());
+    assert_eq!(pending_solicitations.get(&(3, tgt_addr)).unwrap().len(), 1);
+
+    // answered once the target shows up on 2
+    answer_pending_solicitations(&pending_solicitations, (2, tgt_addr)).await;
+    assert_eq!(receiver.try_recv().unwrap(), pending_ns);
+    assert!(receiver.try_recv().is_err());
+    assert!(pending_solicitations.get(&(2, tgt_addr)).is_none());
+
+    // dropped once it expires
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    answer_pending_solicitations(&pending_solicitations, (3, tgt_addr)).await;
+    assert!(receiver.try_recv().is_err());
+}
+
+#[test]
+fn test_na_to_upstream() {
+    let proxied_addr: Ipv6Addr = "2001:db8::1".parse().unwrap();
+    let hwaddr = MacAddr::new(0x02, 0, 0, 0, 0, 1);
+    let router = packets::na_flags(true, false, false);
+    let solicited = packets::na_flags(false, true, false);
+
+    // a unicast NS is answered to its source, with S
+    let ns_origin: Ipv6Addr = "fe80::1".parse().unwrap();
+    let (dst_addr, na) = na_to_upstream(ns_origin, proxied_addr, Some(&hwaddr), router).unwrap();
+    assert_eq!(dst_addr, ns_origin);
+    assert_eq!(
+        packets::parse_NA_packet(na.packet()),
+        Some((router | solicited, Some(hwaddr)))
+    );
+
+    // a DAD NS is answered to all-nodes, without S
+    let (dst_addr, na) =
+        na_to_upstream(Ipv6Addr::UNSPECIFIED, proxied_addr, Some(&hwaddr), router).unwrap();
+    assert_eq!(dst_addr, ALL_NODES);
+    assert_eq!(
+        packets::parse_NA_packet(na.packet()),
+        Some((router, Some(hwaddr)))
+    );
+    assert_eq!(na.get_target_addr(), proxied_addr);
+}
+
+#[test]
+fn test_ns_action() {
+    for (dad, dad_policy, in_use, action) in [
+        // the other NSes do not care about the policy
+        (false, DadPolicy::Defend, true, NSAction::Answer),
+        (false, DadPolicy::Defend, false, NSAction::Forward),
+        (false, DadPolicy::Silent, true, NSAction::Answer),
+        (false, DadPolicy::Silent, false, NSAction::Forward),
+        // defended only when in use
+        (true, DadPolicy::Defend, true, NSAction::Answer),
+        (true, DadPolicy::Defend, false, NSAction::Drop),
+        (true, DadPolicy::Silent, true, NSAction::Drop),
+        (true, DadPolicy::Silent, false, NSAction::Drop),
+        // forwarded, and defended once the target answers
+        (true, DadPolicy::Forward, true, NSAction::Answer),
+        (true, DadPolicy::Forward, false, NSAction::Forward),
+    ] {
+        assert_eq!(
+            ns_action(dad, dad_policy, in_use),
+            action,
+            "dad {} {:?} in use {}",
+            dad,
+            dad_policy,
+            in_use
+        );
+    }
+}
+
+#[test]
+fn test_conflicts() {
+    let now = Instant::now();
+    let later = |secs| now + Duration::from_secs(secs);
+    let tgt_addr: Ipv6Addr = "2001:db8::1".parse().unwrap();
+    let (mine, foreign) = (
+        MacAddr::new(0x02, 0, 0, 0, 0, 1),
+        MacAddr::new(0x02, 0, 0, 0, 0, 2),
+    );
+    let mut conflicts = Conflicts::new(Duration::from_secs(60));
+
+    // a host on upstream interface 1 claims the target with another MAC: reported once, and not answered
+    let observed = Some((now, Some(foreign)));
+    assert_eq!(
+        conflicts.check((1, tgt_addr), observed, mine, now),
+        (true, Some(foreign))
+    );
+    assert_eq!(
+        conflicts.check((1, tgt_addr), observed, mine, later(1)),
+        (true, None)
+    );
+    // nobody else claims it on interface 2
+    assert_eq!(
+        conflicts.check((2, tgt_addr), None, mine, later(1)),
+        (false, None)
+    );
+    // the same MAC is no conflict, e.g. another proxy advertising the VRRP MAC
+    assert_eq!(
+        conflicts.check((1, tgt_addr), Some((now, Some(mine))), mine, later(1)),
+        (false, None)
+    );
+    // answered again after the hold time, and reported again on the next NA
+    assert_eq!(
+        conflicts.check((1, tgt_addr), observed, mine, later(60)),
+        (false, None)
+    );
+    assert_eq!(
+        conflicts.check(
+            (1, tgt_addr),
+            Some((later(70), Some(foreign))),
+            mine,
+            later(70)
+        ),
+        (true, Some(foreign))
+    );
+    // disabled by a zero hold time
+    assert_eq!(
+        Conflicts::new(Duration::ZERO).check((1, tgt_addr), observed, mine, now),
+        (false, None)
+    );
+}
+
+#[test]
+fn test_advertised_hwaddr() {
+    let iface_hwaddr = MacAddr::new(0x02, 0, 0, 0, 0, 1);
+    let vrrp = MacAddr::new(0x00, 0x00, 0x5e, 0x00, 0x01, 0x0a);
+    let neighbor = MacAddr::new(0x02, 0, 0, 0, 0, 3);
+    let unasked = || -> Option<MacAddr> { panic!("the neighbor is asked for") };
+
+    assert_eq!(
+        advertised_hwaddr(AdvertisedHwaddr::Iface, iface_hwaddr, unasked),
+        iface_hwaddr
+    );
+    assert_eq!(
+        advertised_hwaddr(AdvertisedHwaddr::Fixed(vrrp), iface_hwaddr, unasked),
+        vrrp
+    );
+    assert_eq!(
+        advertised_hwaddr(AdvertisedHwaddr::Neighbor, iface_hwaddr, || Some(neighbor)),
+        neighbor
+    );
+    assert_eq!(
+        advertised_hwaddr(AdvertisedHwaddr::Neighbor, iface_hwaddr, || None),
+        iface_hwaddr
+    );
+
+    // the option carries the advertised MAC, and is left out for a unicast NS that reached the upstream MAC
+    for (tgt_hwaddr, unicast_ns, option) in [
+        (iface_hwaddr, false, Some(iface_hwaddr)),
+        (iface_hwaddr, true, None),
+        (vrrp, false, Some(vrrp)),
+        (vrrp, true, Some(vrrp)),
+        (neighbor, true, Some(neighbor)),
+    ] {
+        assert_eq!(
+            tlla_option(tgt_hwaddr, iface_hwaddr, unicast_ns),
+            option,
+            "{} unicast {}",
+            tgt_hwaddr,
+            unicast_ns
+        );
+        let (_, na) = na_to_upstream(
+            "fe80::1".parse().unwrap(),
+            "2001:db8::1".parse().unwrap(),
+            option.as_ref(),
+            0,
+        )
+        .unwrap();
+        assert_eq!(
+            packets::parse_NA_packet(na.packet()),
+            Some((packets::na_flags(false, true, false), option))
+        );
+    }
 }
@@ -0,0 +1,697 @@
+use crate::conf::{DELAY_FIRST_PROBE_TIME, GlobalConfig, ND_HOP_LIMIT, REACHABLE_TIME};
+use crate::datalink::{PacketSender, PacketSenderOpts};
+use crate::error::Error;
+use crate::interfaces::NDInterface;
+use crate::packets;
+use crate::types::{NeighborsCache, SharedKernelNeighbors};
+use log::{debug, info, warn};
+use pnet::packet::Packet;
+use pnet::util::MacAddr;
+use std::collections::HashMap;
+use std::net::{Ipv6Addr, SocketAddrV6};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+/// states of a neighbor cache entry, see RFC 4861 section 7.3.2
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum NeighborState {
+    Incomplete,
+    Reachable,
+    Stale,
+    Delay,
+    Probe,
+    Failed,
+}
+
+#[derive(getset::Getters, getset::CopyGetters, Debug, Clone, PartialEq, Eq)]
+pub struct NeighborEntry {
+    #[get_copy = "pub with_prefix"]
+    state: NeighborState,
+    /// link-layer address of the neighbor, unknown while INCOMPLETE
+    #[get = "pub with_prefix"]
+    hwaddr: Option<MacAddr>,
+    /// the downstream interface where the neighbor lives
+    #[get_copy = "pub with_prefix"]
+    scope_id: u32,
+    /// the last time the reachability of the neighbor is confirmed
+    #[get = "pub with_prefix"]
+    last_confirmed: Option<Instant>,
+    /// when the timer of current state fires
+    deadline: Instant,
+    /// number of NSes sent in INCOMPLETE or PROBE state, or to refresh a STALE one
+    probes: u32,
+    /// when a STALE neighbor is solicited again before it expires, None if it is not refreshed
+    refresh_at: Option<Instant>,
+}
+
+impl NeighborEntry {
+    /// whether I can answer NSes on behalf of the neighbor
+    pub fn is_usable(&self) -> bool {
+        !matches!(
+            self.state,
+            NeighborState::Incomplete | NeighborState::Failed
+        )
+    }
+}
+
+/// a NS the prober should send
+/// the NS is sent to the solicited-node multicast address if hwaddr is None
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct NeighborProbe {
+    pub scope_id: u32,
+    pub addr: Ipv6Addr,
+    pub hwaddr: Option<MacAddr>,
+}
+
+/// the neighbor cache, keyed by (scope id of the downstream interface, address of the neighbor)
+pub struct NeighborTable {
+    entries: Mutex<HashMap<(u32, Ipv6Addr), NeighborEntry>>,
+    /// MAX_MULTICAST_SOLICIT and MAX_UNICAST_SOLICIT
+    max_solicit: u32,
+    retrans_timer: Duration,
+    /// how long an unused STALE or FAILED neighbor stays in cache
+    cache_ttl: Duration,
+    /// how long before its expiry a STALE neighbor is refreshed, zero for never
+    refresh_interval: Duration,
+    /// the refresh starts up to this much earlier, so that the neighbors are not refreshed at once
+    refresh_jitter: Duration,
+}
+
+impl NeighborTable {
+    pub fn new(global: &GlobalConfig) -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+            max_solicit: global.get_ns_retransmits(),
+            retrans_timer: global.get_retrans_timer(),
+            cache_ttl: global.get_cache_ttl(),
+            refresh_interval: global.get_refresh_interval(),
+            refresh_jitter: global.get_refresh_jitter(),
+        }
+    }
+
+    /// how often the timers should be fired
+    pub fn get_retrans_timer(&self) -> Duration {
+        self.retrans_timer
+    }
+
+    pub fn get(&self, key: &(u32, Ipv6Addr)) -> Option<NeighborEntry> {
+        self.entries.lock().unwrap().get(key).cloned()
+    }
+
+    /// whether the neighbor can be proxied,
+    /// using a STALE entry starts the DELAY timer (RFC 4861 section 7.3.3)
+    pub fn reachable(&self, key: &(u32, Ipv6Addr)) -> bool {
+        self.reachable_at(key, Instant::now())
+    }
+
+    fn reachable_at(&self, key: &(u32, Ipv6Addr), now: Instant) -> bool {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get_mut(key) {
+            Some(entry) => {
+                if entry.state == NeighborState::Stale {
+                    entry.state = NeighborState::Delay;
+                    entry.deadline = now + DELAY_FIRST_PROBE_TIME;
+                }
+                entry.is_usable()
+            }
+            None => false,
+        }
+    }
+
+    /// start address resolution for the neighbor,
+    /// returns false if the resolution is already in progress or the neighbor is known
+    pub fn solicit(&self, key: (u32, Ipv6Addr)) -> bool {
+        self.solicit_at(key, Instant::now())
+    }
+
+    fn solicit_at(&self, key: (u32, Ipv6Addr), now: Instant) -> bool {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(entry) = entries.get(&key)
+            && entry.state != NeighborState::Failed
+        {
+            return false;
+        }
+        entries.insert(
+            key,
+            NeighborEntry {
+                state: NeighborState::Incomplete,
+                hwaddr: None,
+                scope_id: key.0,
+                last_confirmed: None,
+                deadline: now + self.retrans_timer,
+                probes: 1,
+                refresh_at: None,
+            },
+        );
+        true
+    }
+
+    /// update the entry with a received NA (RFC 4861 section 7.2.5)
+    pub fn confirm(
+        &self,
+        key: (u32, Ipv6Addr),
+        hwaddr: Option<MacAddr>,
+        solicited: bool,
+        override_flag: bool,
+    ) {
+        self.confirm_at(key, hwaddr, solicited, override_flag, Instant::now())
+    }
+
+    fn confirm_at(
+        &self,
+        key: (u32, Ipv6Addr),
+        hwaddr: Option<MacAddr>,
+        solicited: bool,
+        override_flag: bool,
+        now: Instant,
+    ) {
+        let mut entries = self.entries.lock().unwrap();
+        let reachable = |entry: &mut NeighborEntry| {
+            entry.state = NeighborState::Reachable;
+            entry.last_confirmed = Some(now);
+            entry.deadline = now + REACHABLE_TIME;
+            entry.probes = 0;
+        };
+        let stale = |entry: &mut NeighborEntry| self.make_stale(entry, now);
+        match entries.get_mut(&key) {
+            // RFC 4861 discards such NAs, but an unsolicited NA tells me where the neighbor is
+            None => {
+                let Some(hwaddr) = hwaddr else { return };
+                let mut entry = NeighborEntry {
+                    state: NeighborState::Stale,
+                    hwaddr: Some(hwaddr),
+                    scope_id: key.0,
+                    last_confirmed: None,
+                    deadline: now,
+                    probes: 0,
+                    refresh_at: None,
+                };
+                if solicited {
+                    reachable(&mut entry)
+                } else {
+                    stale(&mut entry)
+                }
+                entries.insert(key, entry);
+            }
+            Some(entry)
+                if matches!(
+                    entry.state,
+                    NeighborState::Incomplete | NeighborState::Failed
+                ) =>
+            {
+                // the NA is useless without a link-layer address
+                let Some(hwaddr) = hwaddr else { return };
+                entry.hwaddr = Some(hwaddr);
+                if solicited {
+                    reachable(entry)
+                } else {
+                    stale(entry)
+                }
+            }
+            Some(entry) => {
+                let changed = hwaddr.is_some() && hwaddr != entry.hwaddr;
+                if !override_flag && changed {
+                    if entry.state == NeighborState::Reachable {
+                        stale(entry)
+                    }
+                    return;
+                }
+                if changed {
+                    entry.hwaddr = hwaddr;
+                }
+                if solicited {
+                    reachable(entry)
+                } else if changed {
+                    stale(entry)
+                }
+            }
+        }
+    }
+
+    /// the entry goes STALE, and expires after cache_ttl unless it is refreshed
+    fn make_stale(&self, entry: &mut NeighborEntry, now: Instant) {
+        entry.state = NeighborState::Stale;
+        entry.deadline = now + self.cache_ttl;
+        entry.probes = 0;
+        entry.refresh_at = match self.refresh_interval.is_zero() {
+            true => None,
+            false => {
+                // a random point in the jitter, differing from neighbor to neighbor
+                let jitter = random_up_to(self.refresh_jitter);
+                Some(
+                    entry
+                        .deadline
+                        .checked_sub(self.refresh_interval + jitter)
+                        .unwrap_or(now)
+                        .max(now),
+                )
+            }
+        };
+    }
+
+    /// fire the timers, returns the NSes that should be sent
+    pub fn tick(&self, now: Instant) -> Vec<NeighborProbe> {
+        let mut probes = Vec::new();
+        let (max_solicit, retrans_timer, cache_ttl) =
+            (self.max_solicit, self.retrans_timer, self.cache_ttl);
+        self.entries
+            .lock()
+            .unwrap()
+            .retain(|(scope_id, addr), entry| {
+                // solicit a STALE neighbor again before it expires, it is REACHABLE again on its NA
+                if entry.state == NeighborState::Stale
+                    && entry.deadline > now
+                    && let Some(refresh_at) = entry.refresh_at
+                    && refresh_at <= now
+                {
+                    entry.probes += 1;
+                    entry.refresh_at = Some(now + retrans_timer)
+                        .filter(|next| entry.probes < max_solicit && *next < entry.deadline);
+                    debug!("Refresh neighbor {}%{} before it expires.", addr, scope_id);
+                    probes.push(NeighborProbe {
+                        scope_id: *scope_id,
+                        addr: *addr,
+                        hwaddr: entry.hwaddr,
+                    });
+                    return true;
+                }
+                if entry.deadline > now {
+                    return true;
+                }
+                match entry.state {
+                    NeighborState::Incomplete if entry.probes < max_solicit => {
+                        entry.probes += 1;
+                        entry.deadline = now + retrans_timer;
+                        probes.push(NeighborProbe {
+                            scope_id: *scope_id,
+                            addr: *addr,
+                            hwaddr: None,
+                        });
+                    }
+                    NeighborState::Probe if entry.probes < max_solicit => {
+                        entry.probes += 1;
+                        entry.deadline = now + retrans_timer;
+                        probes.push(NeighborProbe {
+                            scope_id: *scope_id,
+                            addr: *addr,
+                            hwaddr: entry.hwaddr,
+                        });
+                    }
+                    NeighborState::Incomplete | NeighborState::Probe => {
+                        debug!("Neighbor {}%{} failed to respond.", addr, scope_id);
+                        entry.state = NeighborState::Failed;
+                        entry.deadline = now + cache_ttl;
+                    }
+                    NeighborState::Reachable => self.make_stale(entry, now),
+                    NeighborState::Delay => {
+                        entry.state = NeighborState::Probe;
+                        entry.probes = 1;
+                        entry.deadline = now + retrans_timer;
+                        probes.push(NeighborProbe {
+                            scope_id: *scope_id,
+                            addr: *addr,
+                            hwaddr: entry.hwaddr,
+                        });
+                    }
+                    // garbage collection
+                    NeighborState::Stale | NeighborState::Failed => return false,
+                }
+                true
+            });
+        probes
+    }
+}
+
+/// a target that failed address resolution
+struct NegativeEntry {
+    /// number of consecutive failures
+    failures: u32,
+    held_until: Instant,
+    /// the hold-down has expired, and the target is being solicited again
+    retrying: bool,
+    /// NSes suppressed for the target
+    hits: u64,
+}
+
+/// whether either neighbor oracle still vouches for the neighbor
+pub fn neighbor_usable(
+    neighbors_cache: &NeighborsCache,
+    kernel_neighbors: &SharedKernelNeighbors,
+    key: &(u32, Ipv6Addr),
+) -> bool {
+    neighbors_cache
+        .get(key)
+        .is_some_and(|entry| entry.is_usable())
+        || kernel_neighbors
+            .lock()
+            .unwrap()
+            .get(key)
+            .is_some_and(|neighbor| neighbor.is_usable())
+}
+
+/// remembers the targets that failed address resolution recently,
+/// so that repeated NSes for non-existent hosts do not cause traffic on downstream interfaces
+pub struct NegativeCache {
+    entries: HashMap<Ipv6Addr, NegativeEntry>,
+    capacity: usize,
+    hold_down: Duration,
+    max_hold_down: Duration,
+    /// total number of NSes suppressed by the negative cache
+    hits: u64,
+}
+
+impl NegativeCache {
+    pub fn new(capacity: usize, hold_down: Duration, max_hold_down: Duration) -> Self {
+        Self {
+            entries: HashMap::new(),
+            capacity,
+            hold_down,
+            max_hold_down,
+            hits: 0,
+        }
+    }
+
+    pub fn get_hits(&self) -> u64 {
+        self.hits
+    }
+
+    /// whether the target is held down, the suppressed NS is counted if so
+    pub fn held(&mut self, addr: &Ipv6Addr, now: Instant) -> bool {
+        let Some(entry) = self.entries.get_mut(addr) else {
+            return false;
+        };
+        if now < entry.held_until {
+            entry.hits += 1;
+            self.hits += 1;
+            return true;
+        }
+        // forget the failures that happened long ago
+        if now >= entry.held_until + self.max_hold_down {
+            self.entries.remove(addr);
+        }
+        false
+    }
+
+    /// the address resolution of the target failed
+    ///
+    /// returns the hold-down time, which doubles on every consecutive failure,
+    /// or None if the hold-down has just expired and the target deserves another try
+    pub fn record_failure(&mut self, addr: Ipv6Addr, now: Instant) -> Option<Duration> {
+        if self.capacity == 0 {
+            return None;
+        }
+        if let Some(entry) = self.entries.get_mut(&addr) {
+            if !entry.retrying {
+                entry.retrying = true;
+                return None;
+            }
+            entry.failures += 1;
+            let hold = self
+                .hold_down
+                .saturating_mul(1 << (entry.failures - 1).min(16))
+                .min(self.max_hold_down);
+            entry.held_until = now + hold;
+            entry.retrying = false;
+            return Some(hold);
+        }
+        if self.entries.len() >= self.capacity {
+            self.evict(now);
+        }
+        self.entries.insert(
+            addr,
+            NegativeEntry {
+                failures: 1,
+                held_until: now + self.hold_down,
+                retrying: false,
+                hits: 0,
+            },
+        );
+        Some(self.hold_down)
+    }
+
+    /// the target is reachable again
+    pub fn forget(&mut self, addr: &Ipv6Addr) {
+        if let Some(entry) = self.entries.remove(addr) {
+            debug!(
+                "Negative cache: {} is reachable after {} failures, {} NSes suppressed.",
+                addr, entry.failures, entry.hits
+            );
+        }
+    }
+
+    /// make room for a new entry
+    fn evict(&mut self, now: Instant) {
+        let max_hold_down = self.max_hold_down;
+        self.entries
+            .retain(|_, entry| now < entry.held_until + max_hold_down);
+        if self.entries.len() < self.capacity {
+            return;
+        }
+        if let Some(oldest) = self
+            .entries
+            .iter()
+            .min_by_key(|(_, entry)| entry.held_until)
+            .map(|(addr, _)| *addr)
+        {
+            self.entries.remove(&oldest);
+        }
+    }
+}
+
+/// drives the timers of the neighbor cache,
+/// and sends NSes to downstream interfaces on behalf of it
+pub struct NeighborProber {
+    neighbors_cache: NeighborsCache,
+    pkt_sender: PacketSender,
+    /// downstream interfaces, kept up to date by IfaceMonitor
+    ifaces: watch::Receiver<HashMap<u32, NDInterface>>,
+}
+
+impl NeighborProber {
+    pub fn new(
+        neighbors_cache: NeighborsCache,
+        ifaces: watch::Receiver<HashMap<u32, NDInterface>>,
+    ) -> Result<Self, Error> {
+        let pkt_sender = PacketSender::new()?;
+        pkt_sender.set_multicast_hops_v6(ND_HOP_LIMIT)?;
+        pkt_sender.set_unicast_hops_v6(ND_HOP_LIMIT)?;
+        Ok(Self {
+            neighbors_cache,
+            pkt_sender,
+            ifaces,
+        })
+    }
+
+    pub async fn run(self) -> Result<(), Error> {
+        info!("NeighborProber: Start to work.");
+        let mut interval = tokio::time::interval(self.neighbors_cache.get_retrans_timer());
+        loop {
+            interval.tick().await;
+            for probe in self.neighbors_cache.tick(Instant::now()) {
+                let Some(iface) = self.ifaces.borrow().get(&probe.scope_id).cloned() else {
+                    continue;
+                };
+                let dst_addr = match probe.hwaddr {
+                    Some(_) => probe.addr,
+                    None => address_translation::gen_solicited_node_multicast_address(&probe.addr),
+                };
+                debug!(
+                    "NeighborProber: Send Neighbour Solicition packet for {} to {} on {}.",
+                    probe.addr,
+                    dst_addr,
+                    iface.get_name()
+                );
+                let pkt = match packets::generate_NS_packet(
+                    iface.get_link_addr(),
+                    &dst_addr,
+                    &probe.addr,
+                    Some(iface.get_hwaddr()),
+                ) {
+                    Ok(pkt) => pkt,
+                    Err(e) => {
+                        warn!(
+                            "NeighborProber: _{:?}_ Failed to generate NS for {}.",
+                            e, probe.addr
+                        );
+                        continue;
+                    }
+                };
+                // a failed probe counts as sent, the prober goes on for the other neighbors
+                if let Err(e) = self
+                    .pkt_sender
+                    .send_pkt_to(
+                        pkt.packet(),
+                        &SocketAddrV6::new(dst_addr, 0, 0, probe.scope_id).into(),
+                    )
+                    .await
+                {
+                    warn!(
+                        "NeighborProber: _{:?}_ Failed to send NS for {} to {} on {}.",
+                        e,
+                        probe.addr,
+                        dst_addr,
+                        iface.get_name()
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// a random duration from zero up to max, in milliseconds, for spreading the timers
+pub fn random_up_to(max: Duration) -> Duration {
+    match max.as_millis() as u64 {
+        0 => Duration::ZERO,
+        ms => Duration::from_millis(rand::random_range(0..=ms)),
+    }
+}
+
+#[test]
+fn test_neighbor_state_machine() {
+    use crate::conf::{MAX_MULTICAST_SOLICIT, RETRANS_TIMER, TTL_OF_CACHE};
+    let now = Instant::now();
+    let table = NeighborTable::new(&GlobalConfig::default());
+    let key = (1, "2001:db8::1".parse().unwrap());
+    let hwaddr = MacAddr::new(2, 0, 0, 0, 0, 1);
+
+    // INCOMPLETE -> FAILED after MAX_MULTICAST_SOLICIT NSes
+    assert!(table.solicit_at(key, now));
+    assert!(!table.solicit_at(key, now));
+    assert!(!table.reachable_at(&key, now));
+    let mut t = now;
+    for _ in 1..MAX_MULTICAST_SOLICIT {
+        t += RETRANS_TIMER;
+        assert_eq!(table.tick(t).len(), 1);
+    }
+    t += RETRANS_TIMER;
+    assert!(table.tick(t).is_empty());
+    assert_eq!(table.get(&key).unwrap().get_state(), NeighborState::Failed);
+
+    // FAILED -> INCOMPLETE -> REACHABLE
+    assert!(table.solicit_at(key, t));
+    table.confirm_at(key, Some(hwaddr), true, true, t);
+    assert_eq!(
+        table.get(&key).unwrap().get_state(),
+        NeighborState::Reachable
+    );
+    assert!(table.reachable_at(&key, t));
+
+    // REACHABLE -> STALE -> DELAY -> PROBE
+    t += REACHABLE_TIME;
+    table.tick(t);
+    assert_eq!(table.get(&key).unwrap().get_state(), NeighborState::Stale);
+    assert!(table.reachable_at(&key, t));
+    assert_eq!(table.get(&key).unwrap().get_state(), NeighborState::Delay);
+    t += DELAY_FIRST_PROBE_TIME;
+    assert_eq!(
+        table.tick(t),
+        vec![NeighborProbe {
+            scope_id: key.0,
+            addr: key.1,
+            hwaddr: Some(hwaddr),
+        }]
+    );
+    assert_eq!(table.get(&key).unwrap().get_state(), NeighborState::Probe);
+
+    // an unsolicited NA with a different hwaddr and without O flag does not confirm anything
+    table.confirm_at(key, Some(MacAddr::new(2, 0, 0, 0, 0, 2)), false, false, t);
+    assert_eq!(table.get(&key).unwrap().get_state(), NeighborState::Probe);
+    assert_eq!(*table.get(&key).unwrap().get_hwaddr(), Some(hwaddr));
+
+    // PROBE -> FAILED
+    for _ in 0..MAX_MULTICAST_SOLICIT {
+        t += RETRANS_TIMER;
+        table.tick(t);
+    }
+    assert_eq!(table.get(&key).unwrap().get_state(), NeighborState::Failed);
+    assert!(!table.reachable_at(&key, t));
+
+    // garbage collection
+    t += TTL_OF_CACHE;
+    table.tick(t);
+    assert!(table.get(&key).is_none());
+}
+
+#[test]
+fn test_neighbor_refresh() {
+    use crate::conf::{MAX_MULTICAST_SOLICIT, RETRANS_TIMER, TTL_OF_CACHE};
+    let now = Instant::now();
+    let mut table = NeighborTable::new(&GlobalConfig::default());
+    table.refresh_interval = Duration::from_secs(30);
+    table.refresh_jitter = Duration::from_secs(5);
+    let key = (1, "2001:db8::1".parse().unwrap());
+    let hwaddr = MacAddr::new(2, 0, 0, 0, 0, 1);
+    let probe = NeighborProbe {
+        scope_id: key.0,
+        addr: key.1,
+        hwaddr: Some(hwaddr),
+    };
+
+    // an unused STALE neighbor is solicited by unicast before it expires
+    table.confirm_at(key, Some(hwaddr), false, true, now);
+    let refresh_at = table.entries.lock().unwrap()[&key].refresh_at.unwrap();
+    assert!(refresh_at >= now + TTL_OF_CACHE - Duration::from_secs(35));
+    assert!(refresh_at <= now + TTL_OF_CACHE - Duration::from_secs(30));
+    assert!(table.tick(refresh_at - Duration::from_millis(1)).is_empty());
+    assert_eq!(table.tick(refresh_at), vec![probe]);
+    assert_eq!(table.get(&key).unwrap().get_state(), NeighborState::Stale);
+
+    // and REACHABLE again on its NA, going STALE with a new lifetime later
+    let mut t = refresh_at + RETRANS_TIMER / 2;
+    table.confirm_at(key, Some(hwaddr), true, false, t);
+    assert_eq!(
+        table.get(&key).unwrap().get_state(),
+        NeighborState::Reachable
+    );
+    t += REACHABLE_TIME;
+    assert!(table.tick(t).is_empty());
+    assert_eq!(table.get(&key).unwrap().get_state(), NeighborState::Stale);
+    let refresh_at = table.entries.lock().unwrap()[&key].refresh_at.unwrap();
+    assert!(refresh_at > t + TTL_OF_CACHE - Duration::from_secs(36));
+
+    // a silent neighbor is solicited MAX_MULTICAST_SOLICIT times, and expires as before
+    t = refresh_at;
+    for _ in 0..MAX_MULTICAST_SOLICIT {
+        assert_eq!(table.tick(t), vec![probe]);
+        t += RETRANS_TIMER;
+    }
+    assert!(table.tick(t).is_empty());
+    table.tick(t + Duration::from_secs(40));
+    assert!(table.get(&key).is_none());
+}
+
+#[test]
+fn test_negative_cache() {
+    let now = Instant::now();
+    let hold_down = Duration::from_secs(5);
+    let mut cache = NegativeCache::new(2, hold_down, Duration::from_secs(12));
+    let addr: Ipv6Addr = "2001:db8::1".parse().unwrap();
+
+    assert!(!cache.held(&addr, now));
+    assert_eq!(cache.record_failure(addr, now), Some(hold_down));
+    assert!(cache.held(&addr, now));
+    assert!(cache.held(&addr, now + Duration::from_secs(4)));
+    assert_eq!(cache.get_hits(), 2);
+
+    // hold-down expired, try again, and back off if it fails again
+    let t = now + hold_down;
+    assert!(!cache.held(&addr, t));
+    assert_eq!(cache.record_failure(addr, t), None);
+    assert_eq!(cache.record_failure(addr, t), Some(Duration::from_secs(10)));
+    assert_eq!(cache.record_failure(addr, t), None);
+    assert_eq!(cache.record_failure(addr, t), Some(Duration::from_secs(12)));
+
+    // reachable again
+    cache.forget(&addr);
+    assert!(!cache.held(&addr, t));
+
+    // bounded
+    for i in 0..3u16 {
+        let t = now + Duration::from_millis(i.into());
+        cache.record_failure(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, i), t);
+    }
+    assert_eq!(cache.entries.len(), 2);
+    assert!(!cache.held(&Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0), now));
+    assert!(cache.held(&Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 2), now));
+}
@@ -0,0 +1,80 @@
+use super::{NetlinkAttrs, NetlinkMessage, NetlinkSocket, attr_to_u32};
+use crate::error::Error;
+use std::collections::HashMap;
+
+/// sizeof(struct ifinfomsg)
+const IFINFOMSG_LEN: usize = 16;
+
+/// not in libc yet
+const IFLA_LINKINFO: u16 = 18;
+const IFLA_GROUP: u16 = 27;
+const IFLA_INFO_KIND: u16 = 1;
+
+/// what ip -d link tells about an interface, beyond its name and addresses
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LinkInfo {
+    /// bridge, veth, wireguard, vlan..., None for the physical ones
+    pub kind: Option<String>,
+    /// the interface group, 0 is "default"
+    pub group: u32,
+}
+
+impl LinkInfo {
+    /// (scope id, LinkInfo) of a RTM_NEWLINK message
+    pub fn parse(msg: &NetlinkMessage) -> Option<(u32, Self)> {
+        let hdr = msg.payload.get(0..IFINFOMSG_LEN)?;
+        let kind = msg
+            .attr(IFINFOMSG_LEN, IFLA_LINKINFO)
+            .and_then(|nested| NetlinkAttrs { buf: nested }.find(|(t, _)| *t == IFLA_INFO_KIND))
+            .map(|(_, kind)| {
+                String::from_utf8_lossy(kind)
+                    .trim_end_matches('\0')
+                    .to_string()
+            });
+        Some((
+            u32::from_ne_bytes(hdr[4..8].try_into().unwrap()),
+            Self {
+                kind,
+                group: msg
+                    .attr(IFINFOMSG_LEN, IFLA_GROUP)
+                    .and_then(attr_to_u32)
+                    .unwrap_or_default(),
+            },
+        ))
+    }
+}
+
+/// dump the kind and the group of every interface, keyed by scope id
+pub async fn dump_links(socket: &mut NetlinkSocket) -> Result<HashMap<u32, LinkInfo>, Error> {
+    let mut ifinfomsg = [0u8; IFINFOMSG_LEN];
+    ifinfomsg[0] = libc::AF_UNSPEC as u8;
+    Ok(socket
+        .dump(NetlinkMessage::new(libc::RTM_GETLINK, 0, &ifinfomsg))
+        .await?
+        .iter()
+        .filter(|msg| msg.msg_type == libc::RTM_NEWLINK)
+        .filter_map(LinkInfo::parse)
+        .collect())
+}
+
+#[test]
+fn test_link_info() {
+    let mut ifinfomsg = [0u8; IFINFOMSG_LEN];
+    ifinfomsg[4..8].copy_from_slice(&7u32.to_ne_bytes());
+    let linkinfo = NetlinkMessage::new(0, 0, &[]).push_attr(IFLA_INFO_KIND, b"bridge\0");
+    let msg = NetlinkMessage::new(libc::RTM_NEWLINK, 0, &ifinfomsg)
+        .push_attr(IFLA_GROUP, &3u32.to_ne_bytes())
+        .push_attr(IFLA_LINKINFO, &linkinfo.payload);
+    assert_eq!(
+        LinkInfo::parse(&msg),
+        Some((
+            7,
+            LinkInfo {
+                kind: Some(String::from("bridge")),
+                group: 3
+            }
+        ))
+    );
+    let msg = NetlinkMessage::new(libc::RTM_NEWLINK, 0, &ifinfomsg);
+    assert_eq!(LinkInfo::parse(&msg), Some((7, LinkInfo::default())));
+}
@@ -0,0 +1,287 @@
+mod link;
+mod neigh;
+mod route;
+pub use link::*;
+pub use neigh::*;
+pub use route::*;
+
+use crate::error::Error;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::mem::{MaybeUninit, size_of};
+use std::os::unix::io::AsRawFd;
+use tokio::io::unix::AsyncFd;
+
+/// marks the kernel objects made by me
+pub const RTPROT_NDPROXY: u8 = 110;
+
+const NLMSG_HDRLEN: usize = 16;
+const RTA_HDRLEN: usize = 4;
+/// large enough for a page of dump
+const NETLINK_BUF_SIZE: usize = 65536;
+
+const fn nl_align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+/// a rtnetlink message,
+/// the payload contains the fixed header (ifinfomsg, rtmsg, ...) and the attributes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetlinkMessage {
+    pub msg_type: u16,
+    pub flags: u16,
+    pub seq: u32,
+    pub payload: Vec<u8>,
+}
+
+impl NetlinkMessage {
+    pub fn new(msg_type: u16, flags: u16, header: &[u8]) -> Self {
+        let mut payload = header.to_vec();
+        payload.resize(nl_align(header.len()), 0);
+        Self {
+            msg_type,
+            flags: flags | libc::NLM_F_REQUEST as u16,
+            seq: 0,
+            payload,
+        }
+    }
+
+    /// append a route attribute
+    pub fn push_attr(mut self, attr_type: u16, data: &[u8]) -> Self {
+        self.payload
+            .extend_from_slice(&((RTA_HDRLEN + data.len()) as u16).to_ne_bytes());
+        self.payload.extend_from_slice(&attr_type.to_ne_bytes());
+        self.payload.extend_from_slice(data);
+        self.payload.resize(nl_align(self.payload.len()), 0);
+        self
+    }
+
+    /// the route attributes following a fixed header of header_len bytes
+    pub fn attrs(&self, header_len: usize) -> NetlinkAttrs<'_> {
+        NetlinkAttrs {
+            buf: self.payload.get(nl_align(header_len)..).unwrap_or_default(),
+        }
+    }
+
+    /// get the first attribute of attr_type
+    pub fn attr(&self, header_len: usize, attr_type: u16) -> Option<&[u8]> {
+        self.attrs(header_len)
+            .find(|(t, _)| *t == attr_type)
+            .map(|(_, data)| data)
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut ret = Vec::with_capacity(NLMSG_HDRLEN + self.payload.len());
+        ret.extend_from_slice(&((NLMSG_HDRLEN + self.payload.len()) as u32).to_ne_bytes());
+        ret.extend_from_slice(&self.msg_type.to_ne_bytes());
+        ret.extend_from_slice(&self.flags.to_ne_bytes());
+        ret.extend_from_slice(&self.seq.to_ne_bytes());
+        // port id, 0 means the kernel
+        ret.extend_from_slice(&0u32.to_ne_bytes());
+        ret.extend_from_slice(&self.payload);
+        ret
+    }
+
+    /// split a datagram into messages
+    fn decode(mut buf: &[u8]) -> Vec<Self> {
+        let mut ret = Vec::new();
+        while buf.len() >= NLMSG_HDRLEN {
+            let len = u32::from_ne_bytes(buf[0..4].try_into().unwrap()) as usize;
+            if len < NLMSG_HDRLEN || len > buf.len() {
+                break;
+            }
+            ret.push(Self {
+                msg_type: u16::from_ne_bytes(buf[4..6].try_into().unwrap()),
+                flags: u16::from_ne_bytes(buf[6..8].try_into().unwrap()),
+                seq: u32::from_ne_bytes(buf[8..12].try_into().unwrap()),
+                payload: buf[NLMSG_HDRLEN..len].to_vec(),
+            });
+            buf = &buf[nl_align(len).min(buf.len())..];
+        }
+        ret
+    }
+
+    /// for NLMSG_ERROR, 0 means ACK, otherwise it is a negative errno
+    fn error_code(&self) -> Option<i32> {
+        match self.msg_type == libc::NLMSG_ERROR as u16 {
+            true => self
+                .payload
+                .get(0..4)
+                .map(|v| i32::from_ne_bytes(v.try_into().unwrap())),
+            false => None,
+        }
+    }
+}
+
+/// iterator of (attribute type, attribute data)
+pub struct NetlinkAttrs<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Iterator for NetlinkAttrs<'a> {
+    type Item = (u16, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.buf.len() < RTA_HDRLEN {
+            return None;
+        }
+        let len = u16::from_ne_bytes(self.buf[0..2].try_into().unwrap()) as usize;
+        // NLA_F_NESTED and NLA_F_NET_BYTEORDER are not interesting
+        let attr_type = u16::from_ne_bytes(self.buf[2..4].try_into().unwrap()) & 0x3fff;
+        if len < RTA_HDRLEN || len > self.buf.len() {
+            return None;
+        }
+        let data = &self.buf[RTA_HDRLEN..len];
+        self.buf = &self.buf[nl_align(len).min(self.buf.len())..];
+        Some((attr_type, data))
+    }
+}
+
+/// parse the data of an attribute into an Ipv6Addr
+pub fn attr_to_ipv6(data: &[u8]) -> Option<std::net::Ipv6Addr> {
+    <[u8; 16]>::try_from(data)
+        .ok()
+        .map(std::net::Ipv6Addr::from)
+}
+
+/// parse the data of an attribute into a u32
+pub fn attr_to_u32(data: &[u8]) -> Option<u32> {
+    <[u8; 4]>::try_from(data).ok().map(u32::from_ne_bytes)
+}
+
+/// a NETLINK_ROUTE socket
+pub struct NetlinkSocket {
+    socket: AsyncFd<Socket>,
+    buf: Vec<MaybeUninit<u8>>,
+    seq: u32,
+}
+
+impl NetlinkSocket {
+    /// groups: the multicast groups to subscribe (RTMGRP_*), 0 for none
+    pub fn new(groups: u32) -> Result<Self, Error> {
+        let inner = Socket::new(
+            Domain::from(libc::AF_NETLINK),
+            Type::RAW,
+            Some(Protocol::from(libc::NETLINK_ROUTE)),
+        )?;
+        inner.set_nonblocking(true)?;
+        let mut addr: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as u16;
+        addr.nl_groups = groups;
+        if unsafe {
+            libc::bind(
+                inner.as_raw_fd(),
+                &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                size_of::<libc::sockaddr_nl>() as u32,
+            )
+        } != 0
+        {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(Self {
+            socket: AsyncFd::new(inner)?,
+            buf: vec![MaybeUninit::<u8>::zeroed(); NETLINK_BUF_SIZE],
+            seq: 0,
+        })
+    }
+
+    /// send a message to the kernel, returns its sequence number
+    pub async fn send(&mut self, mut msg: NetlinkMessage) -> Result<u32, Error> {
+        self.seq = self.seq.wrapping_add(1);
+        msg.seq = self.seq;
+        let pkt = msg.encode();
+        loop {
+            match self
+                .socket
+                .writable()
+                .await?
+                .try_io(|socket| socket.get_ref().send(&pkt))
+            {
+                Ok(len) => {
+                    len?;
+                    return Ok(self.seq);
+                }
+                Err(_) => continue,
+            }
+        }
+    }
+
+    /// receive a datagram from the kernel
+    pub async fn recv(&mut self) -> Result<Vec<NetlinkMessage>, Error> {
+        loop {
+            match self
+                .socket
+                .readable()
+                .await?
+                .try_io(|socket| socket.get_ref().recv(&mut self.buf))
+            {
+                Ok(len) => {
+                    let buf: Vec<u8> = self.buf[0..len?]
+                        .iter()
+                        .map(|x| unsafe { x.assume_init() })
+                        .collect();
+                    return Ok(NetlinkMessage::decode(&buf));
+                }
+                Err(_) => continue,
+            }
+        }
+    }
+
+    /// send a request with NLM_F_ACK, and wait for the kernel to acknowledge it
+    pub async fn request(&mut self, msg: NetlinkMessage) -> Result<(), Error> {
+        let mut msg = msg;
+        msg.flags |= libc::NLM_F_ACK as u16;
+        let seq = self.send(msg).await?;
+        loop {
+            for reply in self.recv().await? {
+                if reply.seq != seq {
+                    continue;
+                }
+                match reply.error_code() {
+                    Some(0) => return Ok(()),
+                    Some(errno) => return Err(Error::Netlink(-errno)),
+                    None => continue,
+                }
+            }
+        }
+    }
+
+    /// send a dump request, and collect the replies
+    pub async fn dump(&mut self, msg: NetlinkMessage) -> Result<Vec<NetlinkMessage>, Error> {
+        let mut msg = msg;
+        msg.flags |= libc::NLM_F_DUMP as u16;
+        let seq = self.send(msg).await?;
+        let mut ret = Vec::new();
+        loop {
+            for reply in self.recv().await? {
+                if reply.seq != seq {
+                    continue;
+                }
+                if reply.msg_type == libc::NLMSG_DONE as u16 {
+                    return Ok(ret);
+                }
+                match reply.error_code() {
+                    Some(0) => return Ok(ret),
+                    Some(errno) => return Err(Error::Netlink(-errno)),
+                    None => ret.push(reply),
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_netlink_message() {
+    let msg = NetlinkMessage::new(libc::RTM_GETROUTE, 0, &[libc::AF_INET6 as u8; 12])
+        .push_attr(libc::RTA_OIF, &3u32.to_ne_bytes())
+        .push_attr(libc::RTA_DST, &[0xfe; 3]);
+    let decoded = NetlinkMessage::decode(&msg.encode());
+    assert_eq!(decoded, vec![msg.clone()]);
+    assert_eq!(
+        decoded[0].attrs(12).collect::<Vec<_>>(),
+        vec![
+            (libc::RTA_OIF, &3u32.to_ne_bytes()[..]),
+            (libc::RTA_DST, &[0xfe; 3][..])
+        ]
+    );
+    assert_eq!(msg.attr(12, libc::RTA_OIF).and_then(attr_to_u32), Some(3));
+}
@@ -0,0 +1,254 @@
+use super::{NetlinkMessage, NetlinkSocket, RTPROT_NDPROXY, attr_to_ipv6};
+use crate::error::Error;
+use crate::nd_proxy::answer_pending_solicitations;
+use crate::types::{PendingSolicitations, SharedKernelNeighbors};
+use log::{debug, trace, warn};
+use pnet::util::MacAddr;
+use std::collections::HashMap;
+use std::net::Ipv6Addr;
+
+/// sizeof(struct ndmsg)
+pub const NDMSG_LEN: usize = 12;
+
+/// not in libc yet
+const NDA_PROTOCOL: u16 = 12;
+
+/// the neighbor states that can be trusted
+const NUD_USABLE: u16 = libc::NUD_REACHABLE
+    | libc::NUD_STALE
+    | libc::NUD_DELAY
+    | libc::NUD_PROBE
+    | libc::NUD_PERMANENT
+    | libc::NUD_NOARP;
+
+/// construct a struct ndmsg
+pub fn ndmsg(scope_id: u32, state: u16, flags: u8) -> [u8; NDMSG_LEN] {
+    let mut ret = [0u8; NDMSG_LEN];
+    ret[0] = libc::AF_INET6 as u8;
+    ret[4..8].copy_from_slice(&scope_id.to_ne_bytes());
+    ret[8..10].copy_from_slice(&state.to_ne_bytes());
+    ret[10] = flags;
+    ret
+}
+
+/// construct a RTM_NEWNEIGH or RTM_DELNEIGH message for a proxy entry made by me
+pub fn proxy_neigh(msg_type: u16, flags: u16, addr: Ipv6Addr, scope_id: u32) -> NetlinkMessage {
+    NetlinkMessage::new(
+        msg_type,
+        flags,
+        &ndmsg(scope_id, libc::NUD_PERMANENT, libc::NTF_PROXY),
+    )
+    .push_attr(libc::NDA_DST, &addr.octets())
+    .push_attr(NDA_PROTOCOL, &[RTPROT_NDPROXY])
+}
+
+/// (scope id, address) of a proxy entry made by me
+pub fn parse_proxy_neigh(msg: &NetlinkMessage) -> Option<(u32, Ipv6Addr)> {
+    let hdr = msg.payload.get(0..NDMSG_LEN)?;
+    if hdr[0] != libc::AF_INET6 as u8
+        || hdr[10] & libc::NTF_PROXY == 0
+        || msg.attr(NDMSG_LEN, NDA_PROTOCOL) != Some(&[RTPROT_NDPROXY])
+    {
+        return None;
+    }
+    Some((
+        u32::from_ne_bytes(hdr[4..8].try_into().unwrap()),
+        msg.attr(NDMSG_LEN, libc::NDA_DST).and_then(attr_to_ipv6)?,
+    ))
+}
+
+/// a neighbor in the kernel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KernelNeighbor {
+    /// NUD_*
+    pub state: u16,
+    pub hwaddr: Option<MacAddr>,
+}
+
+impl KernelNeighbor {
+    pub fn is_usable(&self) -> bool {
+        self.state & NUD_USABLE != 0
+    }
+
+    pub fn is_failed(&self) -> bool {
+        self.state & libc::NUD_FAILED != 0
+    }
+}
+
+/// a mirror of the IPv6 neighbor table in the kernel,
+/// keyed by (scope id of the interface, address of the neighbor)
+#[derive(Default)]
+pub struct KernelNeighbors {
+    neighbors: HashMap<(u32, Ipv6Addr), KernelNeighbor>,
+}
+
+impl KernelNeighbors {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, key: &(u32, Ipv6Addr)) -> Option<KernelNeighbor> {
+        self.neighbors.get(key).copied()
+    }
+
+    fn clear(&mut self) {
+        self.neighbors.clear();
+    }
+
+    /// apply a RTM_NEWNEIGH or RTM_DELNEIGH message,
+    /// returns the key of the neighbor if it becomes usable
+    fn update(&mut self, msg: &NetlinkMessage) -> Option<(u32, Ipv6Addr)> {
+        let hdr = msg.payload.get(0..NDMSG_LEN)?;
+        // proxy entries are not neighbors
+        if hdr[0] != libc::AF_INET6 as u8 || hdr[10] & libc::NTF_PROXY != 0 {
+            return None;
+        }
+        let scope_id = u32::from_ne_bytes(hdr[4..8].try_into().unwrap());
+        let addr = msg.attr(NDMSG_LEN, libc::NDA_DST).and_then(attr_to_ipv6)?;
+        let key = (scope_id, addr);
+        if msg.msg_type == libc::RTM_DELNEIGH {
+            self.neighbors.remove(&key);
+            return None;
+        }
+        let neighbor = KernelNeighbor {
+            state: u16::from_ne_bytes(hdr[8..10].try_into().unwrap()),
+            hwaddr: msg
+                .attr(NDMSG_LEN, libc::NDA_LLADDR)
+                .and_then(|v| <[u8; 6]>::try_from(v).ok())
+                .map(|v| MacAddr::new(v[0], v[1], v[2], v[3], v[4], v[5])),
+        };
+        trace!("NeighMonitor: {}%{} {:?}", addr, scope_id, neighbor);
+        let was_usable = self.get(&key).is_some_and(|v| v.is_usable());
+        self.neighbors.insert(key, neighbor);
+        (neighbor.is_usable() && !was_usable).then_some(key)
+    }
+}
+
+/// keeps KernelNeighbors in sync with the kernel
+pub struct NeighMonitor {
+    socket: NetlinkSocket,
+    neighbors: SharedKernelNeighbors,
+    /// NSes that are waiting for the kernel to resolve their targets
+    pending_solicitations: PendingSolicitations,
+}
+
+impl NeighMonitor {
+    pub fn new(
+        neighbors: SharedKernelNeighbors,
+        pending_solicitations: PendingSolicitations,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            socket: NetlinkSocket::new(libc::RTMGRP_NEIGH as u32)?,
+            neighbors,
+            pending_solicitations,
+        })
+    }
+
+    async fn request_dump(&mut self) -> Result<(), Error> {
+        self.socket
+            .send(NetlinkMessage::new(
+                libc::RTM_GETNEIGH,
+                libc::NLM_F_DUMP as u16,
+                &ndmsg(0, 0, 0),
+            ))
+            .await?;
+        Ok(())
+    }
+
+    /// main loop: dump the neighbor table, then follow the changes
+    pub async fn run(mut self) -> Result<(), Error> {
+        warn!("NeighMonitor: Start to work.");
+        self.request_dump().await?;
+        loop {
+            let msgs = match self.socket.recv().await {
+                Ok(v) => v,
+                // the kernel dropped some notifications, start over
+                Err(Error::Io(e)) if e.raw_os_error() == Some(libc::ENOBUFS) => {
+                    warn!(
+                        "NeighMonitor: Lost neighbor notifications, dump the neighbor table again."
+                    );
+                    self.neighbors.lock().unwrap().clear();
+                    self.request_dump().await?;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            let resolved: Vec<_> = {
+                let mut neighbors = self.neighbors.lock().unwrap();
+                msgs.iter()
+                    .filter(|msg| {
+                        msg.msg_type == libc::RTM_NEWNEIGH || msg.msg_type == libc::RTM_DELNEIGH
+                    })
+                    .filter_map(|msg| neighbors.update(msg))
+                    .collect()
+            };
+            // wake up the NDProxies that are waiting for these neighbors
+            for key in resolved {
+                debug!(
+                    "NeighMonitor: {}%{} is resolved by the kernel.",
+                    key.1, key.0
+                );
+                answer_pending_solicitations(&self.pending_solicitations, key).await;
+            }
+        }
+    }
+}
+
+#[test]
+fn test_kernel_neighbors() {
+    let neigh = |msg_type: u16, state: u16, flags: u8| {
+        NetlinkMessage::new(msg_type, 0, &ndmsg(2, state, flags))
+            .push_attr(
+                libc::NDA_DST,
+                &"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets(),
+            )
+            .push_attr(libc::NDA_LLADDR, &[2, 0, 0, 0, 0, 1])
+    };
+    let key = (2, "2001:db8::1".parse().unwrap());
+    let mut neighbors = KernelNeighbors::new();
+
+    assert_eq!(
+        neighbors.update(&neigh(libc::RTM_NEWNEIGH, libc::NUD_INCOMPLETE, 0)),
+        None
+    );
+    assert!(!neighbors.get(&key).unwrap().is_usable());
+    assert_eq!(
+        neighbors.update(&neigh(libc::RTM_NEWNEIGH, libc::NUD_REACHABLE, 0)),
+        Some(key)
+    );
+    assert_eq!(
+        neighbors.get(&key).unwrap().hwaddr,
+        Some(MacAddr::new(2, 0, 0, 0, 0, 1))
+    );
+    assert_eq!(
+        neighbors.update(&neigh(libc::RTM_NEWNEIGH, libc::NUD_STALE, 0)),
+        None
+    );
+    assert_eq!(
+        neighbors.update(&neigh(libc::RTM_NEWNEIGH, libc::NUD_FAILED, 0)),
+        None
+    );
+    assert!(neighbors.get(&key).unwrap().is_failed());
+    neighbors.update(&neigh(libc::RTM_DELNEIGH, 0, 0));
+    assert!(neighbors.get(&key).is_none());
+    // proxy entries are ignored
+    neighbors.update(&neigh(
+        libc::RTM_NEWNEIGH,
+        libc::NUD_PERMANENT,
+        libc::NTF_PROXY,
+    ));
+    assert!(neighbors.get(&key).is_none());
+    // only the proxy entries made by me are recognized
+    assert_eq!(
+        parse_proxy_neigh(&proxy_neigh(libc::RTM_NEWNEIGH, 0, key.1, 2)),
+        Some(key)
+    );
+    assert_eq!(
+        parse_proxy_neigh(&neigh(
+            libc::RTM_NEWNEIGH,
+            libc::NUD_PERMANENT,
+            libc::NTF_PROXY
+        )),
+        None
+    );
+}
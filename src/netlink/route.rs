@@ -0,0 +1,233 @@
+use super::{NetlinkMessage, NetlinkSocket, RTPROT_NDPROXY, attr_to_ipv6, attr_to_u32};
+use crate::error::Error;
+use crate::types::SharedKernelRoutes;
+use ip_network_table_deps_treebitmap::IpLookupTable;
+use log::{debug, trace, warn};
+use std::collections::{BTreeMap, HashMap};
+use std::net::Ipv6Addr;
+
+/// sizeof(struct rtmsg)
+const RTMSG_LEN: usize = 12;
+
+/// a mirror of the IPv6 main routing table in the kernel,
+/// only the output interfaces of unicast routes are kept
+pub struct KernelRoutes {
+    /// (prefix, prefix length) -> the output interface of the best route
+    table: IpLookupTable<Ipv6Addr, u32>,
+    /// (prefix, prefix length) -> { metric -> output interface }
+    routes: HashMap<(Ipv6Addr, u8), BTreeMap<u32, u32>>,
+}
+
+impl Default for KernelRoutes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl KernelRoutes {
+    pub fn new() -> Self {
+        Self {
+            table: IpLookupTable::new(),
+            routes: HashMap::new(),
+        }
+    }
+
+    /// scope id of the interface that the kernel routes the address to
+    pub fn lookup(&self, addr: Ipv6Addr) -> Option<u32> {
+        self.table.longest_match(addr).map(|(_, _, oif)| *oif)
+    }
+
+    fn clear(&mut self) {
+        self.table = IpLookupTable::new();
+        self.routes.clear();
+    }
+
+    /// apply a RTM_NEWROUTE or RTM_DELROUTE message
+    fn update(&mut self, msg: &NetlinkMessage) {
+        let Some(route) = Route::parse(msg) else {
+            return;
+        };
+        let key = (route.dst, route.dst_len);
+        let routes = self.routes.entry(key).or_default();
+        if msg.msg_type == libc::RTM_NEWROUTE {
+            routes.insert(route.metric, route.oif);
+        } else {
+            routes.remove(&route.metric);
+        }
+        match routes.first_key_value() {
+            Some((_, oif)) => {
+                self.table.insert(route.dst, route.dst_len as u32, *oif);
+            }
+            None => {
+                self.routes.remove(&key);
+                self.table.remove(route.dst, route.dst_len as u32);
+            }
+        }
+    }
+}
+
+/// construct a RTM_NEWROUTE or RTM_DELROUTE message for a /128 route made by me in the main table
+pub fn host_route(msg_type: u16, flags: u16, dst: Ipv6Addr, oif: u32) -> NetlinkMessage {
+    let mut rtmsg = [0u8; RTMSG_LEN];
+    rtmsg[0] = libc::AF_INET6 as u8;
+    rtmsg[1] = 128;
+    rtmsg[4] = libc::RT_TABLE_MAIN;
+    rtmsg[5] = RTPROT_NDPROXY;
+    rtmsg[6] = libc::RT_SCOPE_UNIVERSE;
+    rtmsg[7] = libc::RTN_UNICAST;
+    NetlinkMessage::new(msg_type, flags, &rtmsg)
+        .push_attr(libc::RTA_DST, &dst.octets())
+        .push_attr(libc::RTA_OIF, &oif.to_ne_bytes())
+}
+
+/// (destination, output interface) of a /128 route made by me
+pub fn parse_host_route(msg: &NetlinkMessage) -> Option<(Ipv6Addr, u32)> {
+    let hdr = msg.payload.get(0..RTMSG_LEN)?;
+    if hdr[1] != 128 || hdr[5] != RTPROT_NDPROXY {
+        return None;
+    }
+    let route = Route::parse(msg)?;
+    Some((route.dst, route.oif))
+}
+
+/// construct a RTM_GETROUTE message for dumping the IPv6 main table
+pub fn dump_routes() -> NetlinkMessage {
+    let mut rtmsg = [0u8; RTMSG_LEN];
+    rtmsg[0] = libc::AF_INET6 as u8;
+    NetlinkMessage::new(libc::RTM_GETROUTE, libc::NLM_F_DUMP as u16, &rtmsg)
+        // routes of other tables are filtered out again in Route::parse(),
+        // as the kernel only honors it with NETLINK_GET_STRICT_CHK
+        .push_attr(libc::RTA_TABLE, &(libc::RT_TABLE_MAIN as u32).to_ne_bytes())
+}
+
+/// the interesting part of a route
+#[derive(Debug, PartialEq, Eq)]
+struct Route {
+    dst: Ipv6Addr,
+    dst_len: u8,
+    metric: u32,
+    oif: u32,
+}
+
+impl Route {
+    /// only unicast routes of the main table are interesting
+    fn parse(msg: &NetlinkMessage) -> Option<Self> {
+        let hdr = msg.payload.get(0..RTMSG_LEN)?;
+        let flags = u32::from_ne_bytes(hdr[8..12].try_into().unwrap());
+        if hdr[0] != libc::AF_INET6 as u8
+            || hdr[7] != libc::RTN_UNICAST
+            || flags & libc::RTM_F_CLONED != 0
+        {
+            return None;
+        }
+        let table = msg
+            .attr(RTMSG_LEN, libc::RTA_TABLE)
+            .and_then(attr_to_u32)
+            .unwrap_or(hdr[4] as u32);
+        if table != libc::RT_TABLE_MAIN as u32 {
+            return None;
+        }
+        Some(Self {
+            dst: msg
+                .attr(RTMSG_LEN, libc::RTA_DST)
+                .and_then(attr_to_ipv6)
+                .unwrap_or(Ipv6Addr::UNSPECIFIED),
+            dst_len: hdr[1],
+            metric: msg
+                .attr(RTMSG_LEN, libc::RTA_PRIORITY)
+                .and_then(attr_to_u32)
+                .unwrap_or(0),
+            oif: msg.attr(RTMSG_LEN, libc::RTA_OIF).and_then(attr_to_u32)?,
+        })
+    }
+}
+
+/// keeps KernelRoutes in sync with the kernel
+pub struct RouteMonitor {
+    socket: NetlinkSocket,
+    routes: SharedKernelRoutes,
+}
+
+impl RouteMonitor {
+    pub fn new(routes: SharedKernelRoutes) -> Result<Self, Error> {
+        Ok(Self {
+            socket: NetlinkSocket::new(libc::RTMGRP_IPV6_ROUTE as u32)?,
+            routes,
+        })
+    }
+
+    async fn request_dump(&mut self) -> Result<(), Error> {
+        self.socket.send(dump_routes()).await?;
+        Ok(())
+    }
+
+    /// main loop: dump the routing table, then follow the changes
+    pub async fn run(mut self) -> Result<(), Error> {
+        warn!("RouteMonitor: Start to work.");
+        self.request_dump().await?;
+        loop {
+            let msgs = match self.socket.recv().await {
+                Ok(v) => v,
+                // the kernel dropped some notifications, start over
+                Err(Error::Io(e)) if e.raw_os_error() == Some(libc::ENOBUFS) => {
+                    warn!("RouteMonitor: Lost route notifications, dump the routing table again.");
+                    self.routes.lock().unwrap().clear();
+                    self.request_dump().await?;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            let mut routes = self.routes.lock().unwrap();
+            for msg in msgs {
+                if msg.msg_type != libc::RTM_NEWROUTE && msg.msg_type != libc::RTM_DELROUTE {
+                    continue;
+                }
+                trace!("RouteMonitor: {:?}", Route::parse(&msg));
+                routes.update(&msg);
+            }
+            debug!(
+                "RouteMonitor: {} IPv6 prefixes are routed.",
+                routes.routes.len()
+            );
+        }
+    }
+}
+
+#[test]
+fn test_kernel_routes() {
+    let route = |msg_type: u16, dst: &str, dst_len: u8, metric: u32, oif: u32| {
+        let mut rtmsg = [0u8; RTMSG_LEN];
+        rtmsg[0] = libc::AF_INET6 as u8;
+        rtmsg[1] = dst_len;
+        rtmsg[4] = libc::RT_TABLE_MAIN;
+        rtmsg[7] = libc::RTN_UNICAST;
+        NetlinkMessage::new(msg_type, 0, &rtmsg)
+            .push_attr(libc::RTA_DST, &dst.parse::<Ipv6Addr>().unwrap().octets())
+            .push_attr(libc::RTA_PRIORITY, &metric.to_ne_bytes())
+            .push_attr(libc::RTA_OIF, &oif.to_ne_bytes())
+    };
+    let mut routes = KernelRoutes::new();
+    let addr = "2001:db8::1".parse().unwrap();
+
+    routes.update(&route(libc::RTM_NEWROUTE, "::", 0, 1024, 1));
+    routes.update(&route(libc::RTM_NEWROUTE, "2001:db8::", 64, 256, 2));
+    routes.update(&route(libc::RTM_NEWROUTE, "2001:db8::", 64, 128, 3));
+    assert_eq!(routes.lookup(addr), Some(3));
+    routes.update(&route(libc::RTM_DELROUTE, "2001:db8::", 64, 128, 3));
+    assert_eq!(routes.lookup(addr), Some(2));
+    routes.update(&route(libc::RTM_DELROUTE, "2001:db8::", 64, 256, 2));
+    assert_eq!(routes.lookup(addr), Some(1));
+    // the routes installed by autowire
+    routes.update(&host_route(libc::RTM_NEWROUTE, 0, addr, 4));
+    assert_eq!(routes.lookup(addr), Some(4));
+    assert_eq!(
+        parse_host_route(&host_route(libc::RTM_NEWROUTE, 0, addr, 4)),
+        Some((addr, 4))
+    );
+    assert_eq!(
+        parse_host_route(&route(libc::RTM_NEWROUTE, "2001:db8::1", 128, 1024, 4)),
+        None
+    );
+    routes.update(&host_route(libc::RTM_DELROUTE, 0, addr, 4));
+    assert_eq!(routes.lookup(addr), Some(1));
+}
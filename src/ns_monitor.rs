@@ -1,10 +1,9 @@
 use crate::datalink::{PacketReceiver, PacketReceiverOpts};
 use crate::error::Error;
 use crate::interfaces::NDInterface;
-use crate::types::SharedNSPacketSender;
-use ip_network_table_deps_treebitmap::IpLookupTable;
-use log::{debug, error, trace, warn};
-use std::net::Ipv6Addr;
+use crate::types::NSRoutingTable;
+use log::{debug, trace, warn};
+use tokio::sync::watch;
 
 /// monitors for Neighbor Solicitation
 /// the received packet will be sent to the corresponding NDProxy via mpsc
@@ -13,19 +12,20 @@ use std::net::Ipv6Addr;
 pub struct NSMonitor {
     #[get_mut = "pub with_prefix"]
     inner: PacketReceiver,
+    /// swapped by IfaceMonitor when the proxied prefixes change
     #[get = "pub with_prefix"]
-    #[set = "pub with_prefix"]
-    routing_table: IpLookupTable<Ipv6Addr, SharedNSPacketSender>,
+    routing_table: watch::Receiver<NSRoutingTable>,
     #[get = "pub with_prefix"]
     iface: NDInterface,
 }
 
 impl NSMonitor {
     pub fn new(
-        routing_table: IpLookupTable<Ipv6Addr, SharedNSPacketSender>,
+        routing_table: watch::Receiver<NSRoutingTable>,
         iface: NDInterface,
+        recv_buffer_size: usize,
     ) -> Result<Self, Error> {
-        let inner = PacketReceiver::new()?;
+        let inner = PacketReceiver::new(recv_buffer_size)?;
         inner.bind_to_interface(&iface)?;
         inner.set_allmulti(&iface)?;
         inner.set_filter_pass_ipv6_ns()?;
@@ -65,14 +65,19 @@ impl NSMonitor {
                     tgt_addr,
                 );
             }
+            let route = self
+                .routing_table
+                .borrow()
+                .longest_match(*tgt_addr)
+                .map(|(pfx, pfx_len, sender)| (pfx, pfx_len, sender.clone()));
             // logging again
             debug!(
                 "NSMonitor for {}: Get route for 🔍{}🔍 - {:?}",
                 self.iface.get_name(),
                 tgt_addr,
-                self.routing_table.longest_match(*tgt_addr)
+                route
             );
-            if let Some((pfx, _pfx_len, sender)) = self.routing_table.longest_match(*tgt_addr) {
+            if let Some((pfx, _pfx_len, sender)) = route {
                 // NOT forwarding NS for some special addresses
                 //     1. https://datatracker.ietf.org/doc/html/rfc4291#section-2.6.1
                 if pfx == *tgt_addr {
@@ -83,12 +88,12 @@ impl NSMonitor {
                     .send((*self.iface.get_scope_id(), tgt_addr, shared_packet))
                     .await
                 {
-                    error!(
-                        "NSMonitor for {}: _{:?}_ Failed to send the packet to its corresponding proxy.",
+                    // the proxy is being stopped, its route goes away soon
+                    debug!(
+                        "NSMonitor for {}: _{:?}_ The proxy has gone, drop the packet.",
                         self.iface.get_name(),
                         e
                     );
-                    return Err(Error::Mpsc(e));
                 };
             }
         }
@@ -0,0 +1,324 @@
+use crate::conf::RETRANS_TIMER;
+use crate::error::Error;
+use crate::neighbors::neighbor_usable;
+use crate::netlink::{NetlinkMessage, NetlinkSocket, ndmsg, parse_proxy_neigh, proxy_neigh};
+use crate::types::*;
+use log::{info, warn};
+use std::collections::HashMap;
+use std::net::Ipv6Addr;
+use std::time::{Duration, Instant};
+
+/// how often the sysctls of an interface are checked again
+const SYSCTL_RECHECK: Duration = Duration::from_secs(10);
+
+/// how an offloaded proxy entry goes away
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProxyLifetime {
+    /// the explicit hosts of static rules, until I exit
+    Permanent,
+    /// static rules, until no NS is seen for the cache TTL of the rule
+    Idle(Duration),
+    /// forward rules, until the downstream neighbor (scope id, rewritten address) is no longer usable
+    Neighbor(u32, Ipv6Addr),
+    /// the targets leased by a DHCPv6 server, until the lease ends, None for infinite leases
+    Lease(Option<Instant>),
+}
+
+/// a proxy entry in the kernel
+#[derive(getset::CopyGetters, Debug, Clone, Copy)]
+pub struct ProxyEntry {
+    #[get_copy = "pub with_prefix"]
+    lifetime: ProxyLifetime,
+    last_used: Instant,
+}
+
+impl ProxyEntry {
+    /// neighbor_usable tells whether the downstream neighbor (scope id, rewritten address) is still there
+    fn expired(
+        &self,
+        now: Instant,
+        neighbor_usable: impl FnOnce(&(u32, Ipv6Addr)) -> bool,
+    ) -> bool {
+        match self.lifetime {
+            ProxyLifetime::Permanent => false,
+            ProxyLifetime::Idle(ttl) => now.duration_since(self.last_used) > ttl,
+            ProxyLifetime::Lease(end) => end.is_some_and(|end| now >= end),
+            ProxyLifetime::Neighbor(nei_scope_id, rewrited_addr) => {
+                !neighbor_usable(&(nei_scope_id, rewrited_addr))
+            }
+        }
+    }
+}
+
+/// programs the kernel to answer NSes for proxied addresses by itself,
+/// `ip -6 neigh add proxy <address> dev <upstream interface>`
+///     0. removes the entries left by the last run on start
+///     1. NDProxies ask for an entry once they answer a NS
+///     2. the entry is removed once its lifetime ends
+pub struct ProxyOffload {
+    socket: NetlinkSocket,
+    receiver: OffloadReceiver,
+    /// (scope id of the upstream interface, proxied address) -> entry
+    entries: SharedProxyEntries,
+    neighbors_cache: NeighborsCache,
+    kernel_neighbors: SharedKernelNeighbors,
+    /// scope id -> (whether the kernel answers NSes on the interface, when it was checked)
+    sysctls: HashMap<u32, (bool, Instant)>,
+}
+
+impl ProxyOffload {
+    pub fn new(
+        receiver: OffloadReceiver,
+        entries: SharedProxyEntries,
+        neighbors_cache: NeighborsCache,
+        kernel_neighbors: SharedKernelNeighbors,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            socket: NetlinkSocket::new(0)?,
+            receiver,
+            entries,
+            neighbors_cache,
+            kernel_neighbors,
+            sysctls: HashMap::new(),
+        })
+    }
+
+    /// main loop: reconcile the entries in the kernel, then follow the requests
+    pub async fn run(mut self) -> Result<(), Error> {
+        warn!("ProxyOffload: Start to work.");
+        self.remove_leftovers().await?;
+        let mut interval = tokio::time::interval(RETRANS_TIMER);
+        loop {
+            tokio::select! {
+                // the branch is disabled once every NDProxy has gone
+                Some((scope_id, addr, lifetime)) = self.receiver.recv() => {
+                    self.offload(scope_id, addr, lifetime).await
+                }
+                _ = interval.tick() => self.remove_expired().await,
+                // the leases end on time, instead of on the next tick
+                () = lease_ended(&self.entries) => self.remove_expired().await,
+            }
+        }
+    }
+
+    /// the entries made by the last run are stale, the NDProxies will ask for them again
+    async fn remove_leftovers(&mut self) -> Result<(), Error> {
+        let leftovers: Vec<_> = self
+            .socket
+            .dump(NetlinkMessage::new(
+                libc::RTM_GETNEIGH,
+                0,
+                &ndmsg(0, 0, libc::NTF_PROXY),
+            ))
+            .await?
+            .iter()
+            .filter_map(parse_proxy_neigh)
+            .collect();
+        for (scope_id, addr) in leftovers {
+            info!(
+                "ProxyOffload: Remove the stale proxy entry of {} on scope id {}.",
+                addr, scope_id
+            );
+            remove_entry(&mut self.socket, scope_id, addr).await?;
+        }
+        Ok(())
+    }
+
+    async fn offload(&mut self, scope_id: u32, addr: Ipv6Addr, lifetime: ProxyLifetime) {
+        let now = Instant::now();
+        if let Some(entry) = self.entries.lock().unwrap().get_mut(&(scope_id, addr))
+            && entry.lifetime == lifetime
+        {
+            entry.last_used = now;
+            return;
+        }
+        // the kernel ignores proxy entries without these sysctls
+        if !self.kernel_answers(scope_id, now) {
+            return;
+        }
+        match self
+            .socket
+            .request(proxy_neigh(
+                libc::RTM_NEWNEIGH,
+                (libc::NLM_F_CREATE | libc::NLM_F_REPLACE) as u16,
+                addr,
+                scope_id,
+            ))
+            .await
+        {
+            Ok(()) => {
+                info!(
+                    "ProxyOffload: The kernel answers NSes for {} on scope id {} from now on.",
+                    addr, scope_id
+                );
+                self.entries.lock().unwrap().insert(
+                    (scope_id, addr),
+                    ProxyEntry {
+                        lifetime,
+                        last_used: now,
+                    },
+                );
+            }
+            Err(e) => warn!(
+                "ProxyOffload: _{:?}_ Failed to add the proxy entry of {} on scope id {}.",
+                e, addr, scope_id
+            ),
+        }
+    }
+
+    /// whether proxy_ndp and forwarding are enabled on the interface
+    fn kernel_answers(&mut self, scope_id: u32, now: Instant) -> bool {
+        if let Some((enabled, checked)) = self.sysctls.get(&scope_id)
+            && now.duration_since(*checked) < SYSCTL_RECHECK
+        {
+            return *enabled;
+        }
+        let enabled = match iface_name(scope_id) {
+            Some(name) => {
+                (sysctl_enabled("all", "proxy_ndp") || sysctl_enabled(&name, "proxy_ndp"))
+                    && sysctl_enabled(&name, "forwarding")
+            }
+            None => false,
+        };
+        if !enabled {
+            warn!(
+                "ProxyOffload: proxy_ndp or forwarding is disabled on scope id {}, keep answering NSes myself.",
+                scope_id
+            );
+        }
+        self.sysctls.insert(scope_id, (enabled, now));
+        enabled
+    }
+
+    async fn remove_expired(&mut self) {
+        let now = Instant::now();
+        let expired: Vec<_> = self
+            .entries
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, entry)| {
+                entry.expired(now, |key| {
+                    neighbor_usable(&self.neighbors_cache, &self.kernel_neighbors, key)
+                })
+            })
+            .map(|(key, _)| *key)
+            .collect();
+        for (scope_id, addr) in expired {
+            info!(
+                "ProxyOffload: The proxy entry of {} on scope id {} has expired.",
+                addr, scope_id
+            );
+            if let Err(e) = remove_entry(&mut self.socket, scope_id, addr).await {
+                warn!(
+                    "ProxyOffload: _{:?}_ Failed to remove the proxy entry of {}.",
+                    e, addr
+                );
+            }
+            self.entries.lock().unwrap().remove(&(scope_id, addr));
+        }
+    }
+}
+
+/// wait for the first lease of the entries to end
+async fn lease_ended(entries: &SharedProxyEntries) {
+    let end = entries
+        .lock()
+        .unwrap()
+        .values()
+        .filter_map(|entry| match entry.lifetime {
+            ProxyLifetime::Lease(end) => end,
+            _ => None,
+        })
+        .min();
+    match end {
+        Some(end) => tokio::time::sleep_until(end.into()).await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn remove_entry(
+    socket: &mut NetlinkSocket,
+    scope_id: u32,
+    addr: Ipv6Addr,
+) -> Result<(), Error> {
+    match socket
+        .request(proxy_neigh(libc::RTM_DELNEIGH, 0, addr, scope_id))
+        .await
+    {
+        // the kernel removes the entries of vanished interfaces by itself
+        Err(Error::Netlink(libc::ENOENT)) | Err(Error::Netlink(libc::ENODEV)) => Ok(()),
+        result => result,
+    }
+}
+
+fn iface_name(scope_id: u32) -> Option<String> {
+    let mut buf = [0u8; libc::IF_NAMESIZE];
+    if unsafe { libc::if_indextoname(scope_id, buf.as_mut_ptr() as *mut libc::c_char) }.is_null() {
+        return None;
+    }
+    std::ffi::CStr::from_bytes_until_nul(&buf)
+        .ok()
+        .map(|name| name.to_string_lossy().into_owned())
+}
+
+fn sysctl_enabled(iface: &str, key: &str) -> bool {
+    std::fs::read_to_string(format!("/proc/sys/net/ipv6/conf/{}/{}", iface, key))
+        .is_ok_and(|v| v.trim() != "0")
+}
+
+/// remove every proxy entry made by me, on exit
+pub async fn remove_all(entries: SharedProxyEntries) -> Result<(), Error> {
+    remove_where(&entries, |_, _| true).await
+}
+
+/// remove the proxy entries of (scope id, address) picked by f, such as the ones of a stopped rule
+pub async fn remove_where(
+    entries: &SharedProxyEntries,
+    f: impl Fn(u32, Ipv6Addr) -> bool,
+) -> Result<(), Error> {
+    let entries: Vec<_> = entries
+        .lock()
+        .unwrap()
+        .extract_if(|(scope_id, addr), _| f(*scope_id, *addr))
+        .map(|(key, _)| key)
+        .collect();
+    if entries.is_empty() {
+        return Ok(());
+    }
+    let mut socket = NetlinkSocket::new(0)?;
+    for (scope_id, addr) in entries {
+        info!("ProxyOffload: Remove the proxy entry of {}.", addr);
+        if let Err(e) = remove_entry(&mut socket, scope_id, addr).await {
+            warn!(
+                "ProxyOffload: _{:?}_ Failed to remove the proxy entry of {}.",
+                e, addr
+            );
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_proxy_lifetime() {
+    let now = Instant::now();
+    let entry = |lifetime| ProxyEntry {
+        lifetime,
+        last_used: now,
+    };
+    let later = |secs| now + Duration::from_secs(secs);
+    let addr = "2001:db8::1".parse().unwrap();
+
+    assert!(!entry(ProxyLifetime::Permanent).expired(later(86400), |_| false));
+    let idle = entry(ProxyLifetime::Idle(Duration::from_secs(30)));
+    assert!(!idle.expired(later(30), |_| true));
+    assert!(idle.expired(later(31), |_| true));
+    // a lease is withdrawn right when it ends
+    let lease = entry(ProxyLifetime::Lease(Some(later(60))));
+    assert!(!lease.expired(later(59), |_| true));
+    assert!(lease.expired(later(60), |_| true));
+    assert!(!entry(ProxyLifetime::Lease(None)).expired(later(86400), |_| false));
+    let neighbor = entry(ProxyLifetime::Neighbor(2, addr));
+    assert!(!neighbor.expired(now, |key| *key == (2, addr)));
+    assert!(neighbor.expired(now, |key| *key == (3, addr)));
+}
@@ -1,5 +1,7 @@
 use pnet::packet::Packet;
-use pnet::packet::icmpv6::ndp::{MutableNeighborSolicitPacket, NeighborSolicitPacket};
+use pnet::packet::icmpv6::ndp::{
+    MutableNeighborSolicitPacket, NeighborAdvertFlags, NeighborSolicitPacket,
+};
 use pnet::packet::icmpv6::{Icmpv6Types, ndp};
 use pnet::util::MacAddr;
 use std::net::Ipv6Addr;
@@ -7,30 +9,56 @@ use std::net::Ipv6Addr;
 use crate::error::Error;
 use crate::types::*;
 
+/// the flags of a proxied Neighbor Advertisement
+///
+/// router: the R flag, for proxying routers
+/// solicited: the S flag, set in the replies to the NSes from unicast addresses
+/// override_flag: the O flag, proxies usually leave it clear (RFC 4861 section 7.2.8)
+pub fn na_flags(router: bool, solicited: bool, override_flag: bool) -> u8 {
+    let mut flags = 0;
+    if router {
+        flags |= NeighborAdvertFlags::Router;
+    }
+    if solicited {
+        flags |= NeighborAdvertFlags::Solicited;
+    }
+    if override_flag {
+        flags |= NeighborAdvertFlags::Override;
+    }
+    flags
+}
+
 /// generate a Neighbor Advertisement packet, necessary information should be provided
+///
+/// tgt_hwaddr: the link-layer address advertised for proxied_addr,
+///             the upstream interface, a fixed MAC or the downstream neighbor,
+///             None leaves the Target Link-Layer Address option out
+/// flag: see na_flags()
 #[allow(non_snake_case)]
 pub fn generate_NA_forwarded<'a>(
     src_addr: &Ipv6Addr,
     dst_addr: &Ipv6Addr,
     proxied_addr: &Ipv6Addr,
-    src_hwaddr: &MacAddr,
+    tgt_hwaddr: Option<&MacAddr>,
     flag: u8,
 ) -> Result<ndp::NeighborAdvertPacket<'a>, Error> {
-    let pkt_buf: Vec<u8> = vec![0; 32];
+    let pkt_buf: Vec<u8> = vec![0; if tgt_hwaddr.is_some() { 32 } else { 24 }];
     let mut ret = ndp::MutableNeighborAdvertPacket::owned(pkt_buf)
         .ok_or(Error::PacketGeneration(NDTypes::NeighborAdv))?;
     // basic info
     ret.set_icmpv6_type(Icmpv6Types::NeighborAdvert);
     // set the to-be-announced addr
     ret.set_target_addr(*proxied_addr);
-    // force O flag to be 0
-    ret.set_flags(flag & 0xdf);
+    ret.set_flags(flag);
     // NS option: target link local address
-    let new_options: Vec<ndp::NdpOption> = vec![ndp::NdpOption {
-        option_type: ndp::NdpOptionTypes::TargetLLAddr,
-        length: 1,
-        data: src_hwaddr.octets().to_vec(),
-    }];
+    let new_options: Vec<ndp::NdpOption> = tgt_hwaddr
+        .map(|hwaddr| ndp::NdpOption {
+            option_type: ndp::NdpOptionTypes::TargetLLAddr,
+            length: 1,
+            data: hwaddr.octets().to_vec(),
+        })
+        .into_iter()
+        .collect();
     ret.set_options(&new_options);
     // icmpv6 cehcksum
     let csum = pnet::util::ipv6_checksum(
@@ -46,6 +74,22 @@ pub fn generate_NA_forwarded<'a>(
     Ok(ret.consume_to_immutable())
 }
 
+/// extract the flags and the target link-layer address from a Neighbor Advertisement packet
+///
+/// pkt: the ICMPv6 part of the packet
+#[allow(non_snake_case)]
+pub fn parse_NA_packet(pkt: &[u8]) -> Option<(u8, Option<MacAddr>)> {
+    let na = ndp::NeighborAdvertPacket::new(pkt)?;
+    let tgt_hwaddr = na
+        .get_options_iter()
+        .find(|opt| opt.get_option_type() == ndp::NdpOptionTypes::TargetLLAddr)
+        .and_then(|opt| match opt.payload() {
+            [a, b, c, d, e, f, ..] => Some(MacAddr::new(*a, *b, *c, *d, *e, *f)),
+            _ => None,
+        });
+    Some((na.get_flags(), tgt_hwaddr))
+}
+
 /// taking over the process of Neighbor Discovery myself
 ///
 /// src_addr: my src addr
@@ -92,4 +136,26 @@ pub fn generate_NS_packet<'a>(
     Ok(ret.consume_to_immutable())
 }
 
-//TODO: tests
+#[test]
+fn test_na_flags() {
+    assert_eq!(na_flags(false, false, false), 0x00);
+    assert_eq!(na_flags(true, false, false), 0x80);
+    assert_eq!(na_flags(false, true, false), 0x40);
+    assert_eq!(na_flags(false, false, true), 0x20);
+    assert_eq!(na_flags(true, true, true), 0xe0);
+
+    let (src_addr, dst_addr): (Ipv6Addr, Ipv6Addr) =
+        ("fe80::1".parse().unwrap(), "fe80::2".parse().unwrap());
+    let tgt_addr: Ipv6Addr = "2001:db8::1".parse().unwrap();
+    let hwaddr = MacAddr::new(2, 0, 0, 0, 0, 1);
+    for flags in [0x00, 0x40, 0x60, 0xc0, 0xe0] {
+        let na =
+            generate_NA_forwarded(&src_addr, &dst_addr, &tgt_addr, Some(&hwaddr), flags).unwrap();
+        // the flags are the first byte after the ICMPv6 header
+        assert_eq!(na.packet()[4], flags);
+        assert_eq!(parse_NA_packet(na.packet()), Some((flags, Some(hwaddr))));
+    }
+    let na = generate_NA_forwarded(&src_addr, &dst_addr, &tgt_addr, None, 0x40).unwrap();
+    assert_eq!(na.packet().len(), 24);
+    assert_eq!(parse_NA_packet(na.packet()), Some((0x40, None)));
+}
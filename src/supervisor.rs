@@ -0,0 +1,546 @@
+use crate::autowire::{self, Autowire};
+use crate::conf::{self, GlobalConfig, NDConfig};
+use crate::error::Error;
+use crate::iface_monitor::{IfaceMonitor, ProxyControl, ProxyControlSender, ProxySubscriber};
+use crate::nd_proxy::{self, NDProxy};
+use crate::neighbors::{NeighborProber, NeighborTable};
+use crate::netlink::{KernelNeighbors, KernelRoutes, NeighMonitor, RouteMonitor};
+use crate::offload::{self, ProxyOffload};
+use crate::types::*;
+use ipnet::Ipv6Net;
+use log::{error, info, warn};
+use r_cache::cache::Cache;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::sync::mpsc;
+use tokio::task::{AbortHandle, JoinSet};
+
+/// a running NDProxy
+struct RunningProxy {
+    config: NDConfig,
+    handle: AbortHandle,
+    /// what the NDProxy is following, for withdrawing its proxy entries and routes
+    ifaces: ProxyIfacesReceiver,
+    prefix: ProxiedPrefixReceiver,
+}
+
+impl RunningProxy {
+    /// the prefix that the targets are rewritten into, where Autowire routes them
+    fn local_prefix(&self) -> Ipv6Net {
+        match self.config.get_address_mangling() {
+            AddressMangling::Nochange => *self.prefix.borrow(),
+            _ => *self.config.get_dst_pfx(),
+        }
+    }
+}
+
+/// what a reload does to a rule
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Reload {
+    Keep,
+    /// only the interfaces have changed, IfaceMonitor applies them to the running NDProxy
+    UpdateIfaces,
+    Restart,
+    Start,
+    Stop,
+}
+
+/// compare the running rules with the new ones, by name,
+/// the rules that have gone come first, so that their prefixes are free for the new ones
+fn reload_plan<'a>(
+    running: impl Iterator<Item = (&'a String, &'a NDConfig)>,
+    configs: &HashMap<String, NDConfig>,
+) -> Vec<(String, Reload)> {
+    let running: HashMap<_, _> = running.collect();
+    let mut plan: Vec<_> = running
+        .keys()
+        .filter(|name| !configs.contains_key(**name))
+        .map(|name| (String::clone(name), Reload::Stop))
+        .chain(configs.iter().map(|(name, config)| {
+            let action = match running.get(name) {
+                None => Reload::Start,
+                Some(old) if *old == config => Reload::Keep,
+                Some(old) if old.same_except_ifaces(config) => Reload::UpdateIfaces,
+                Some(_) => Reload::Restart,
+            };
+            (name.clone(), action)
+        }))
+        .collect();
+    plan.sort_by(|(name1, action1), (name2, action2)| {
+        (*action1 != Reload::Stop, name1).cmp(&(*action2 != Reload::Stop, name2))
+    });
+    plan
+}
+
+/// starts, stops and updates the NDProxies, along with the tasks they need,
+/// the NDProxies are reloaded on SIGHUP
+pub struct Supervisor {
+    /// if any of the shared tasks failed, I exit
+    tasks: JoinSet<Result<(), Error>>,
+    /// the tunables that the running tasks were started with
+    global: GlobalConfig,
+    /// keyed by the name of rule
+    proxies: HashMap<String, RunningProxy>,
+    iface_control: ProxyControlSender,
+    neighbors_cache: NeighborsCache,
+    pending_solicitations: PendingSolicitations,
+    observed_nas: ObservedNAs,
+    kernel_routes: SharedKernelRoutes,
+    route_monitor: bool,
+    kernel_neighbors: SharedKernelNeighbors,
+    neigh_monitor: bool,
+    host_routes: SharedHostRoutes,
+    autowire_sender: AutowireSender,
+    /// taken once Autowire has started
+    autowire_receiver: Option<AutowireReceiver>,
+    proxy_entries: SharedProxyEntries,
+    offload_sender: OffloadSender,
+    /// taken once ProxyOffload has started
+    offload_receiver: Option<OffloadReceiver>,
+}
+
+impl Supervisor {
+    /// start the NDProxies of the config, and the monitors
+    pub fn start(global: GlobalConfig, configs: Vec<NDConfig>) -> Result<Self, Error> {
+        let neighbors_cache = Arc::new(NeighborTable::new(&global));
+        let pending_solicitations = Arc::new(Cache::new(Some(global.pending_ns_ttl())));
+        let observed_nas = Arc::new(Cache::new(Some(conf::OBSERVED_NA_TTL)));
+        // prepare monitors for Neighbor Solicitations and Neighbor Advertisements,
+        // they come and go with the interfaces
+        let mut iface_monitor = IfaceMonitor::new(
+            neighbors_cache.clone(),
+            pending_solicitations.clone(),
+            observed_nas.clone(),
+            global.get_recv_buffer_size(),
+            global.get_learning_rate(),
+        )?;
+        let (autowire_sender, autowire_receiver) = mpsc::channel(global.get_queue_depth());
+        let (offload_sender, offload_receiver) = mpsc::channel(global.get_queue_depth());
+        let mut supervisor = Self {
+            tasks: JoinSet::new(),
+            global,
+            proxies: HashMap::new(),
+            iface_control: iface_monitor.controller(),
+            neighbors_cache: neighbors_cache.clone(),
+            pending_solicitations: pending_solicitations.clone(),
+            observed_nas: observed_nas.clone(),
+            kernel_routes: Arc::new(Mutex::new(KernelRoutes::new())),
+            route_monitor: false,
+            kernel_neighbors: Arc::new(Mutex::new(KernelNeighbors::new())),
+            neigh_monitor: false,
+            host_routes: Arc::new(Mutex::new(HashMap::new())),
+            autowire_sender,
+            autowire_receiver: Some(autowire_receiver),
+            proxy_entries: Arc::new(Mutex::new(HashMap::new())),
+            offload_sender,
+            offload_receiver: Some(offload_receiver),
+        };
+
+        // prepare proxies for proxied_prefixes
+        for config in configs {
+            let name = config.get_name().clone();
+            iface_monitor.add_proxy(name, supervisor.start_proxy(config)?);
+        }
+
+        // maintain the neighbor cache, and probe neighbors on downstream interfaces
+        let prober = NeighborProber::new(neighbors_cache, iface_monitor.subscribe_downstream())?;
+        supervisor.tasks.spawn(prober.run());
+        supervisor.tasks.spawn(iface_monitor.run());
+
+        // reclaim the memory of expired pending NSes and observed NAs
+        supervisor.tasks.spawn(nd_proxy::expire_cache(
+            pending_solicitations,
+            global.pending_ns_ttl(),
+        ));
+        supervisor
+            .tasks
+            .spawn(nd_proxy::expire_cache(observed_nas, conf::OBSERVED_NA_TTL));
+        Ok(supervisor)
+    }
+
+    /// main loop: reload the config on SIGHUP, until SIGINT, SIGTERM, or any shared task failed
+    pub async fn run(mut self, config_filename: String) -> Result<(), Error> {
+        let mut sighup = signal(SignalKind::hangup())?;
+        let mut sigint = signal(SignalKind::interrupt())?;
+        let mut sigterm = signal(SignalKind::terminate())?;
+        let result = loop {
+            tokio::select! {
+                Some(joined) = self.tasks.join_next_with_id() => {
+                    let (id, result) = match joined {
+                        // the stopped NDProxies
+                        Err(e) if e.is_cancelled() => continue,
+                        Err(e) => (e.id(), Err(Error::JoinErrorTokio(e))),
+                        Ok((id, result)) => (id, result),
+                    };
+                    // a failed NDProxy does not take the other rules down with it
+                    let Some(name) = self
+                        .proxies
+                        .iter()
+                        .find(|(_, running)| running.handle.id() == id)
+                        .map(|(name, _)| name.clone())
+                    else {
+                        break result;
+                    };
+                    error!(
+                        "_{:?}_ Rule {} has failed, stop it until the config is reloaded.",
+                        result, name
+                    );
+                    self.stop_proxy(&name).await;
+                    self.control(ProxyControl::Remove(name));
+                }
+                _ = sighup.recv() => self.reload(&config_filename).await,
+                _ = sigint.recv() => break Ok(()),
+                _ = sigterm.recv() => break Ok(()),
+            }
+        };
+        warn!("Shutting down.");
+        self.tasks.abort_all();
+
+        // leave the routing table and the neighbor table as they were
+        if let Err(e) = autowire::unwire_all(self.host_routes).await {
+            error!(
+                "_{:?}_ Failed to remove the routes installed by autowire.",
+                e
+            );
+        }
+        if let Err(e) = offload::remove_all(self.proxy_entries).await {
+            error!("_{:?}_ Failed to remove the proxy entries.", e);
+        }
+        result
+    }
+
+    /// apply the differences between the running NDProxies and the config file
+    async fn reload(&mut self, config_filename: &str) {
+        warn!("Reloading {}.", config_filename);
+        let mut configs: HashMap<_, _> = match conf::parse_config(config_filename) {
+            Ok((global, v)) => {
+                // the rules get the new defaults, the shared tasks do not
+                if global != self.global {
+                    warn!("[global] has changed, restart me to apply it to all of the rules.");
+                }
+                v.into_iter()
+                    .map(|config| (config.get_name().clone(), config))
+                    .collect()
+            }
+            Err(e) => {
+                error!(
+                    "_{:?}_ Failed to parse {}, keep running the old config.",
+                    e, config_filename
+                );
+                return;
+            }
+        };
+
+        let plan = reload_plan(
+            self.proxies
+                .iter()
+                .map(|(name, running)| (name, &running.config)),
+            &configs,
+        );
+        for (name, action) in plan {
+            match (action, configs.remove(&name)) {
+                (Reload::Keep, _) => {}
+                (Reload::Stop, _) => {
+                    info!(
+                        "Rule {} has been removed from {}, stop it.",
+                        name,
+                        self.proxies[&name].config.get_source().display()
+                    );
+                    self.stop_proxy(&name).await;
+                    self.control(ProxyControl::Remove(name));
+                }
+                (Reload::UpdateIfaces, Some(config)) => {
+                    info!(
+                        "The interfaces of rule {} in {} have changed, update it.",
+                        name,
+                        config.get_source().display()
+                    );
+                    let _ = self.iface_control.send(ProxyControl::UpdateIfaces(
+                        name.clone(),
+                        config.get_proxied_ifaces().clone(),
+                        config.get_forwarded_ifaces().clone(),
+                    ));
+                    if let Some(running) = self.proxies.get_mut(&name) {
+                        running.config = config;
+                    }
+                }
+                (action @ (Reload::Restart | Reload::Start), Some(config)) => {
+                    match action {
+                        Reload::Restart => info!(
+                            "Rule {} in {} has changed, restart it.",
+                            name,
+                            config.get_source().display()
+                        ),
+                        _ => info!(
+                            "Rule {} has been added to {}, start it.",
+                            name,
+                            config.get_source().display()
+                        ),
+                    }
+                    self.stop_proxy(&name).await;
+                    match self.start_proxy(config) {
+                        Ok(subscriber) => self.control(ProxyControl::Add(name, subscriber)),
+                        Err(e) => {
+                            error!("_{:?}_ Failed to start rule {}.", e, name);
+                            self.control(ProxyControl::Remove(name));
+                        }
+                    }
+                }
+                // reload_plan gives the rules of configs only
+                (_, None) => {}
+            }
+        }
+    }
+
+    fn control(&self, control: ProxyControl) {
+        // IfaceMonitor never stops unless I exit
+        let _ = self.iface_control.send(control);
+    }
+
+    /// stop a NDProxy, and withdraw the proxy entries and routes made for it
+    async fn stop_proxy(&mut self, name: &str) {
+        let Some(running) = self.proxies.remove(name) else {
+            return;
+        };
+        running.handle.abort();
+        // the targets routed to a more specific rule are left to it
+        let (proxied_prefix, local_prefix) = (*running.prefix.borrow(), running.local_prefix());
+        let others: Vec<_> = self
+            .proxies
+            .values()
+            .map(|other| (*other.prefix.borrow(), other.local_prefix()))
+            .collect();
+        let (upstream_ifs, downstream_ifs): (HashSet<_>, HashSet<_>) = {
+            let ifaces = running.ifaces.borrow();
+            (
+                ifaces.0.keys().copied().collect(),
+                ifaces.1.keys().copied().collect(),
+            )
+        };
+        if let Err(e) = offload::remove_where(&self.proxy_entries, |scope_id, addr| {
+            upstream_ifs.contains(&scope_id)
+                && proxied_prefix.contains(&addr)
+                && !others.iter().any(|(other, _)| {
+                    other.prefix_len() > proxied_prefix.prefix_len() && other.contains(&addr)
+                })
+        })
+        .await
+        {
+            error!(
+                "_{:?}_ Failed to remove the proxy entries of rule {}.",
+                e, name
+            );
+        }
+        if let Err(e) = autowire::unwire_where(&self.host_routes, |addr, scope_id| {
+            downstream_ifs.contains(&scope_id)
+                && local_prefix.contains(&addr)
+                && !others.iter().any(|(_, other)| {
+                    other.prefix_len() > local_prefix.prefix_len() && other.contains(&addr)
+                })
+        })
+        .await
+        {
+            error!("_{:?}_ Failed to remove the routes of rule {}.", e, name);
+        }
+    }
+
+    /// start a NDProxy, and the tasks it needs
+    fn start_proxy(&mut self, config: NDConfig) -> Result<ProxySubscriber, Error> {
+        self.start_tasks_for(&config)?;
+        let name = config.get_name().clone();
+        // remember the interfaces defined by config, so that they can be followed
+        let iface_names = (
+            config.get_proxied_ifaces().clone(),
+            config.get_forwarded_ifaces().clone(),
+        );
+        // and the interface that the proxied prefix is taken from
+        let prefix_source = config
+            .get_proxied_pfx_iface()
+            .clone()
+            .map(|iface| (iface, config.get_proxied_pfx().prefix_len()));
+        //
+        let mut ndproxy = NDProxy::new(
+            config.clone(),
+            self.neighbors_cache.clone(),
+            (
+                self.pending_solicitations.clone(),
+                self.observed_nas.clone(),
+            ),
+            self.kernel_routes.clone(),
+            self.kernel_neighbors.clone(),
+            (self.autowire_sender.clone(), self.host_routes.clone()),
+            (self.offload_sender.clone(), self.proxy_entries.clone()),
+        )?;
+        // route prefix to its corresponding ndproxy
+        let subscriber = ProxySubscriber {
+            proxied_ifaces: iface_names.0,
+            forwarded_ifaces: iface_names.1,
+            learning: *config.get_proxy_type() == Proxy::Forward && *config.get_learning(),
+            observe_nas: !config.get_na_delay().is_zero()
+                || !config.get_na_jitter().is_zero()
+                || !config.get_conflict_hold_time().is_zero(),
+            ifaces_sender: ndproxy.get_ifaces_sender_mut().take().unwrap_or_else(|| {
+                panic!(
+                    "cannot take ifaces sender from ndproxy of {}",
+                    ndproxy.get_proxied_prefix()
+                )
+            }),
+            proxied_prefix: match prefix_source {
+                Some(_) => None,
+                None => Some(*ndproxy.get_proxied_prefix()),
+            },
+            local_prefix: match config.get_address_mangling() {
+                AddressMangling::Nochange => None,
+                _ => Some(*config.get_dst_pfx()),
+            },
+            prefix_source,
+            prefix_sender: ndproxy.get_prefix_sender_mut().take().unwrap_or_else(|| {
+                panic!(
+                    "cannot take prefix sender from ndproxy of {}",
+                    ndproxy.get_proxied_prefix()
+                )
+            }),
+            ns_sender: ndproxy.get_mpsc_sender_mut().take().unwrap_or_else(|| {
+                panic!(
+                    "cannot take mpsc sender from ndproxy of {}",
+                    ndproxy.get_proxied_prefix()
+                )
+            }),
+        };
+        let handle = self.tasks.spawn(ndproxy.run());
+        self.proxies.insert(
+            name,
+            RunningProxy {
+                config,
+                handle,
+                ifaces: subscriber.ifaces_sender.subscribe(),
+                prefix: subscriber.prefix_sender.subscribe(),
+            },
+        );
+        Ok(subscriber)
+    }
+
+    /// the tasks are started on demand, and never stopped
+    fn start_tasks_for(&mut self, config: &NDConfig) -> Result<(), Error> {
+        let forward = *config.get_proxy_type() == Proxy::Forward;
+        // follow the routing table of the host, if any of the proxies asks for it
+        if !self.route_monitor && config.get_forwarded_ifaces().is_auto() {
+            self.tasks
+                .spawn(RouteMonitor::new(self.kernel_routes.clone())?.run());
+            self.route_monitor = true;
+        }
+        // follow the neighbor table of the host, if any of the proxies trusts it
+        if !self.neigh_monitor && forward && *config.get_neighbor_oracle() == NeighborOracle::Kernel
+        {
+            self.tasks.spawn(
+                NeighMonitor::new(
+                    self.kernel_neighbors.clone(),
+                    self.pending_solicitations.clone(),
+                )?
+                .run(),
+            );
+            self.neigh_monitor = true;
+        }
+        // route the confirmed neighbors, if any of the proxies asks for it
+        if forward
+            && *config.get_autowire()
+            && let Some(receiver) = self.autowire_receiver.take()
+        {
+            self.tasks.spawn(
+                Autowire::new(
+                    receiver,
+                    self.host_routes.clone(),
+                    self.neighbors_cache.clone(),
+                    self.kernel_neighbors.clone(),
+                )?
+                .run(),
+            );
+        }
+        // let the kernel answer NSes, if any of the proxies asks for it
+        if *config.get_kernel_offload()
+            && let Some(receiver) = self.offload_receiver.take()
+        {
+            self.tasks.spawn(
+                ProxyOffload::new(
+                    receiver,
+                    self.proxy_entries.clone(),
+                    self.neighbors_cache.clone(),
+                    self.kernel_neighbors.clone(),
+                )?
+                .run(),
+            );
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_reload_plan() {
+    let parse = |rules: &str| -> HashMap<String, NDConfig> {
+        conf::parse_rules(
+            "test.toml",
+            &config::Config::builder()
+                .add_source(config::File::from_str(rules, config::FileFormat::Toml))
+                .build()
+                .unwrap(),
+            &GlobalConfig::default(),
+        )
+        .unwrap()
+        .into_iter()
+        .map(|config| (config.get_name().clone(), config))
+        .collect()
+    };
+    let rule = |name, prefix, ifaces| {
+        format!(
+            "[ndp.{}]\ntype = \"forward\"\nproxied_prefix = \"{}\"\n\
+             proxied_ifaces = \"{}\"\nforwarded_ifaces = \"eth1\"\n",
+            name, prefix, ifaces
+        )
+    };
+    let running = parse(
+        &[
+            rule("kept", "2001:db8:1::/64", "eth0"),
+            rule("moved", "2001:db8:2::/64", "eth0"),
+            rule("changed", "2001:db8:3::/64", "eth0"),
+            rule("removed", "2001:db8:4::/64", "eth0"),
+        ]
+        .concat(),
+    );
+    let configs = parse(
+        &[
+            rule("kept", "2001:db8:1::/64", "eth0"),
+            rule("moved", "2001:db8:2::/64", "eth2"),
+            rule("changed", "2001:db8:3::/56", "eth0"),
+            rule("added", "2001:db8:5::/64", "eth0"),
+        ]
+        .concat(),
+    );
+    assert_eq!(
+        reload_plan(running.iter(), &configs),
+        vec![
+            (String::from("removed"), Reload::Stop),
+            (String::from("added"), Reload::Start),
+            (String::from("changed"), Reload::Restart),
+            (String::from("kept"), Reload::Keep),
+            (String::from("moved"), Reload::UpdateIfaces),
+        ]
+    );
+    assert_eq!(
+        reload_plan(running.iter(), &running),
+        ["changed", "kept", "moved", "removed"]
+            .into_iter()
+            .map(|name| (String::from(name), Reload::Keep))
+            .collect::<Vec<_>>()
+    );
+
+    // "auto" picks the interfaces otherwise, it is not a change of the interfaces only
+    let auto = parse(&rule("moved", "2001:db8:2::/64", "eth0").replace("\"eth1\"", "\"auto\""));
+    assert_eq!(
+        reload_plan(running.iter(), &auto)
+            .into_iter()
+            .filter(|(_, action)| *action != Reload::Stop)
+            .collect::<Vec<_>>(),
+        vec![(String::from("moved"), Reload::Restart)]
+    );
+}
@@ -1,14 +1,74 @@
+use crate::interfaces::NDInterface;
+use crate::neighbors::NeighborTable;
+use crate::netlink::{KernelNeighbors, KernelRoutes};
+use crate::offload::{ProxyEntry, ProxyLifetime};
+use ip_network_table_deps_treebitmap::IpLookupTable;
+use ipnet::Ipv6Net;
+use pnet::util::MacAddr;
 use r_cache::cache::Cache;
-use std::{net::Ipv6Addr, sync::Arc};
-use tokio::sync::mpsc;
+use std::collections::HashMap;
+use std::net::Ipv6Addr;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::sync::{mpsc, watch};
 
 pub type SharedNSPacket = (u32, Box<Ipv6Addr>, Box<Vec<u8>>);
 pub type SharedNSPacketSender = mpsc::Sender<SharedNSPacket>;
 pub type SharedNSPacketReceiver = mpsc::Receiver<SharedNSPacket>;
 
+/// proxied prefix -> the NDProxy, for NSMonitors to dispatch NSes
+pub type NSRoutingTable = IpLookupTable<Ipv6Addr, SharedNSPacketSender>;
+
 /// caches the result of neighbour discovery
-/// u32 is the scope id of the object
-pub type NeighborsCache = Arc<Cache<(u32, Ipv6Addr), ()>>;
+pub type NeighborsCache = Arc<NeighborTable>;
+
+/// (upstream interfaces, downstream interfaces) of a NDProxy, keyed by scope id
+pub type ProxyIfaces = (HashMap<u32, NDInterface>, HashMap<u32, NDInterface>);
+pub type ProxyIfacesSender = watch::Sender<ProxyIfaces>;
+pub type ProxyIfacesReceiver = watch::Receiver<ProxyIfaces>;
+
+/// the proxied prefix of a NDProxy, for the ones following the address of an interface
+pub type ProxiedPrefixSender = watch::Sender<Ipv6Net>;
+pub type ProxiedPrefixReceiver = watch::Receiver<Ipv6Net>;
+
+/// the prefixes that LearningMonitors learn the neighbors of, keyed by the scope id of the downstream interface
+pub type LearningPrefixes = HashMap<u32, Vec<Ipv6Net>>;
+
+/// the routes in the kernel, for "auto" forwarded_ifaces
+pub type SharedKernelRoutes = Arc<Mutex<KernelRoutes>>;
+
+/// the neighbors in the kernel, for the "kernel" neighbor oracle
+pub type SharedKernelNeighbors = Arc<Mutex<KernelNeighbors>>;
+
+/// a NS that is waiting for its target to show up on downstream interfaces
+/// (scope id of the upstream interface, source address of the NS, target address of the NS)
+pub type PendingNS = (u32, Ipv6Addr, Ipv6Addr);
+pub type PendingNSSender = mpsc::Sender<PendingNS>;
+pub type PendingNSReceiver = mpsc::Receiver<PendingNS>;
+
+/// NSes forwarded to downstream that are still waiting for NAs
+/// u32 is the scope id of the downstream interface, Ipv6Addr is the (rewritten) target address
+/// the sender leads to the NDProxy that forwarded the NS
+pub type PendingSolicitations = Arc<Cache<(u32, Ipv6Addr), Vec<(PendingNS, PendingNSSender)>>>;
+
+/// the NAs seen by NAMonitors, (scope id, target address) -> (when the last one was seen, its target MAC),
+/// a delayed NA is cancelled if its target is advertised by someone else in the meantime,
+/// and a target advertised with another MAC is in conflict
+pub type ObservedNAs = Arc<Cache<(u32, Ipv6Addr), (Instant, Option<MacAddr>)>>;
+
+/// asks Autowire to route (the rewritten target address) to (the scope id of the downstream interface)
+pub type AutowireSender = mpsc::Sender<(Ipv6Addr, u32)>;
+pub type AutowireReceiver = mpsc::Receiver<(Ipv6Addr, u32)>;
+
+/// the /128 routes installed by Autowire, target address -> scope id of the output interface
+pub type SharedHostRoutes = Arc<Mutex<HashMap<Ipv6Addr, u32>>>;
+
+/// asks ProxyOffload to make the kernel answer NSes for (the proxied address) on (the scope id of the upstream interface)
+pub type OffloadSender = mpsc::Sender<(u32, Ipv6Addr, ProxyLifetime)>;
+pub type OffloadReceiver = mpsc::Receiver<(u32, Ipv6Addr, ProxyLifetime)>;
+
+/// the proxy entries in the kernel made by ProxyOffload, keyed by (scope id, proxied address)
+pub type SharedProxyEntries = Arc<Mutex<HashMap<(u32, Ipv6Addr), ProxyEntry>>>;
 
 #[derive(Debug)]
 pub enum NDTypes {
@@ -42,6 +102,47 @@ pub enum AddressMangling {
     Npt,
 }
 
+// who tells whether a target exists on downstream interfaces
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum NeighborOracle {
+    Ndproxy,
+    Kernel,
+}
+
+// lease files of DHCPv6 servers
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum LeaseFormat {
+    Dnsmasq,
+    Isc,
+    Kea,
+}
+
+// what to do with the NSes of Duplicate Address Detection, whose source is ::
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum DadPolicy {
+    Defend,
+    Silent,
+    Forward,
+}
+
+// whether the proxied NAs override the cached link-layer addresses of their targets
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum OverridePolicy {
+    Never,
+    Always,
+}
+
+// the link-layer address in the Target Link-Layer Address option of the proxied NAs
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum AdvertisedHwaddr {
+    /// the upstream interface
+    Iface,
+    /// a fixed one, e.g. the virtual MAC of VRRP
+    Fixed(MacAddr),
+    /// the downstream neighbor, for bridged setups
+    Neighbor,
+}
+
 #[test]
 fn test_my_enums() {
     assert!(AddressMangling::Netmap == AddressMangling::Netmap);
@@ -50,4 +151,6 @@ fn test_my_enums() {
 
     assert!(Proxy::Static == Proxy::Static);
     assert!(Proxy::Static != Proxy::Forward);
+
+    assert!(NeighborOracle::Kernel != NeighborOracle::Ndproxy);
 }